@@ -1,5 +1,6 @@
 use log::*;
 use std::path::PathBuf;
+use serde_derive::{Serialize, Deserialize};
 use web3::{Transport, BatchTransport, transports};
 use failure::{Error, ResultExt};
 use futures::future::Future;
@@ -14,17 +15,63 @@ pub struct Client<T: Transport> where web3::transports::batch::Batch<T>: Transpo
     pub web3_batch: web3::Web3<web3::transports::batch::Batch<T>>,
     transport: T,
     ev_loop: tokio_core::reactor::Core,
+    node: NodeInfo,
+}
+
+/// the node a client talks to, and the chain it follows
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct NodeInfo {
+    /// `eth_chainId`, or the network id on nodes that predate it
+    pub chain_id: u64,
+    /// `net_version`
+    pub network_id: u64,
+    /// `web3_clientVersion`
+    pub client_version: String,
+    pub url: String,
 }
 
 impl<T> Client<T> where T: BatchTransport + Clone {
 
-    pub fn new(transport: T) -> Result<Self, Error> {
+    pub fn new(transport: T, url: String) -> Result<Self, Error> {
         let ev_loop = tokio_core::reactor::Core::new().context(ErrorKind::Async)?;
-        Ok(Client {
+        Client {
             web3: web3::Web3::new(transport.clone()),
             web3_batch: web3::Web3::new(web3::transports::Batch::new(transport.clone())),
+            node: NodeInfo::default(),
             transport, ev_loop,
-        })
+        }.identify(url)
+    }
+
+    /// ask the node which chain it follows, and what it is
+    fn identify(mut self, url: String) -> Result<Self, Error> {
+        let network = self.ev_loop.run(self.web3.net().version())
+            .map_err(|e| ErrorKind::Network(format!("{}", e)))?;
+        let network_id = network.parse().context(ErrorKind::Parse)?;
+        // EIP-155 chain id; the network id usually matches, but not on every chain (ie Ethereum Classic)
+        let chain_id = match self.ev_loop.run(self.transport.execute("eth_chainId", vec![])) {
+            Ok(serde_json::Value::String(ref id)) if id.starts_with("0x") => {
+                u64::from_str_radix(&id[2..], 16).context(ErrorKind::Parse)?
+            },
+            Ok(serde_json::Value::Null) => network_id,
+            Ok(v) => {
+                error!("Unexpected chain id {}", v);
+                return Err(ErrorKind::Parse.into());
+            },
+            Err(e) => {
+                debug!("eth_chainId not supported ({}); using network id {}", e, network_id);
+                network_id
+            }
+        };
+        let client_version = self.ev_loop.run(self.web3.web3().client_version())
+            .map_err(|e| ErrorKind::Network(format!("{}", e)))?;
+        info!("Connected to {} on chain {}", client_version, chain_id);
+        self.node = NodeInfo { chain_id, network_id, client_version, url };
+        Ok(self)
+    }
+
+    /// the node this client talks to
+    pub fn node(&self) -> &NodeInfo {
+        &self.node
     }
 
     pub fn remote(&self) -> tokio_core::reactor::Remote {
@@ -116,12 +163,13 @@ impl HttpBuilder {
         };
 
 
-        Ok(Client {
+        Client {
             web3: web3::Web3::new(http.clone()),
             web3_batch: web3::Web3::new(web3::transports::batch::Batch::new(http.clone())),
             transport: http,
+            node: NodeInfo::default(),
             ev_loop,
-        })
+        }.identify(url.clone())
     }
 }
 
@@ -158,12 +206,13 @@ impl IpcBuilder {
             }
         };
 
-        Ok(Client {
+        Client {
             web3: web3::Web3::new(ipc.clone()),
             web3_batch: web3::Web3::new(web3::transports::batch::Batch::new(ipc.clone())),
             transport: ipc,
+            node: NodeInfo::default(),
             ev_loop,
-        })
+        }.identify(path.to_string_lossy().to_string())
     }
 }
 
@@ -13,19 +13,35 @@ use web3::{
 };
 
 use self::config_file::{ConfigFile, Transport as Transport};
+pub use self::config_file::Explorer;
 use super::client::Client;
 use super::err::{ErrorKind, ConfMsg};
+use super::etherscan;
 
-pub use self::cli::Action;
+pub use self::cli::{Action, CacheCommand, TxRef, ExportFormat, Source, Duplicates};
 
 pub struct Configuration {
     file: Option<ConfigFile>,
     log_level: LogLevel,
-    url: String,
-    transport: Transport,
+    /// url and transport of the node. `None` for actions that work offline
+    node: Option<(String, Transport)>,
+    /// `--etherscan-key`, which takes precedence over the key in the configuration file
+    etherscan_key: Option<String>,
+    /// `--explorer`, which takes precedence over the explorer in the configuration file
+    explorer: Option<Explorer>,
     pub action: Action,
 }
 
+/// the block explorer to use for a chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplorerConf {
+    pub explorer: Explorer,
+    /// API endpoint
+    pub url: String,
+    pub api_key: Option<String>,
+    pub requests_per_second: Option<f64>,
+}
+
 pub enum ChosenClient {
     Http(Client<Http>),
     Ipc(Client<Ipc>),
@@ -44,29 +60,73 @@ impl Configuration {
 
     pub fn new() -> Result<Self, Error> {
         let opts = self::cli::parse()?;
-        let (file, url, transport) = url_or_file(opts.file, opts.url, opts.transport)?;
         let action = opts.action;
+        let (file, node) = if action.needs_node() {
+            let (file, url, transport) = url_or_file(opts.file, opts.url, opts.transport)?;
+            (file, Some((url, transport)))
+        } else {
+            (opts.file, None)
+        };
         Ok(Configuration {
-            file, url, transport, action,
+            file, node, action,
             log_level: opts.log_level,
+            etherscan_key: opts.etherscan_key,
+            explorer: opts.explorer,
         })
     }
 
     // get a configured client
     pub fn get_client(&self) -> Result<ChosenClient, Error> {
-        match self.transport {
-            Transport::Http => Ok(ChosenClient::Http(Client::<Http>::new_http(self)?)),
-            Transport::Ipc => Ok(ChosenClient::Ipc(Client::<Ipc>::new_ipc(self)?)),
-            _ => unimplemented!(),
+        match self.node.as_ref().map(|(_, t)| t) {
+            Some(Transport::Http) => Ok(ChosenClient::Http(Client::<Http>::new_http(self)?)),
+            Some(Transport::Ipc) => Ok(ChosenClient::Ipc(Client::<Ipc>::new_ipc(self)?)),
+            Some(_) => unimplemented!(),
+            None => Err(ErrorKind::InvalidConfiguration(ConfMsg::NotFound("Node".to_string())).into()),
+        }
+    }
+
+    /// the explorer of chain `chain_id`: its `[[explorers]]` profile, or the `[etherscan]` section,
+    /// overridden by the command line
+    pub fn explorer(&self, chain_id: u64) -> Result<ExplorerConf, Error> {
+        let file = self.file.as_ref();
+        let profile = file
+            .and_then(|f| f.explorers().iter().find(|e| e.chain_id == Some(chain_id)))
+            .or_else(|| file.and_then(|f| f.etherscan()).filter(|e| e.chain_id.map(|c| c == chain_id).unwrap_or(true)))
+            .cloned()
+            .unwrap_or_default();
+        let profile_explorer = profile.explorer.unwrap_or_default();
+        let explorer = self.explorer.unwrap_or(profile_explorer);
+        // the URL of a profile is for the API of its own explorer
+        let url = if explorer == profile_explorer { profile.url } else { None };
+        let url = match url.or_else(|| etherscan::default_url(explorer, chain_id).map(|u| u.to_string())) {
+            Some(url) => url,
+            None => {
+                error!("No {} URL is known for chain {}; add an [[explorers]] profile for it", explorer, chain_id);
+                return Err(ErrorKind::InvalidConfiguration(ConfMsg::NotFound(format!("{} URL for chain {}", explorer, chain_id))).into());
+            }
+        };
+        if !is_http_url(&url) {
+            error!("The {} URL of chain {} is invalid", explorer, chain_id);
+            return Err(ErrorKind::InvalidConfiguration(ConfMsg::InvalidUrl(url)).into());
+        }
+        let mut api_key = self.etherscan_key.clone().or(profile.api_key);
+        // the default configuration is written with an empty key
+        if api_key.as_ref().map(|k| k.is_empty()).unwrap_or(false) {
+            api_key = None;
+        }
+        if let Some(rate) = profile.requests_per_second.filter(|r| !is_rate(*r)) {
+            error!("The {} profile of chain {} allows an invalid number of requests per second", explorer, chain_id);
+            return Err(ErrorKind::InvalidConfiguration(ConfMsg::InvalidRate(rate.to_string())).into());
         }
+        Ok(ExplorerConf { requests_per_second: profile.requests_per_second, explorer, url, api_key })
     }
 
     pub fn url(&self) -> String {
-        self.url.clone()
+        self.node.as_ref().map(|(url, _)| url.clone()).unwrap_or_default()
     }
 
     pub fn ipc_path(&self) -> PathBuf {
-        PathBuf::from(&self.url)
+        PathBuf::from(self.url())
     }
 }
 
@@ -117,3 +177,40 @@ fn url_or_file(file: Option<ConfigFile>, url: Option<String>, transport: Option<
         }
     }
 }
+
+/// whether `url` is an absolute http or https URL
+fn is_http_url(url: &str) -> bool {
+    match url.parse::<hyper::Uri>() {
+        Ok(uri) => (uri.scheme_str() == Some("http") || uri.scheme_str() == Some("https")) && uri.host().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// whether `rate` requests per second can be waited for
+fn is_rate(rate: f64) -> bool {
+    rate.is_finite() && rate > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_only_accept_http_urls() {
+        assert!(is_http_url("https://api-ropsten.etherscan.io/api"));
+        assert!(is_http_url("http://localhost:4000/api"));
+        assert!(!is_http_url("api.etherscan.io/api"));
+        assert!(!is_http_url("ftp://api.etherscan.io/api"));
+        assert!(!is_http_url("https://api.ether scan.io/api"));
+    }
+
+    #[test]
+    fn it_should_only_accept_positive_rates() {
+        assert!(is_rate(0.2));
+        assert!(is_rate(5.0));
+        assert!(!is_rate(0.0));
+        assert!(!is_rate(-1.0));
+        assert!(!is_rate(std::f64::INFINITY));
+        assert!(!is_rate(std::f64::NAN));
+    }
+}
@@ -1,18 +1,92 @@
 use log::*;
 use std::path::PathBuf;
 use std::str::FromStr;
-use web3::types::{Address};
-use clap::{App, load_yaml, value_t};
+use web3::types::{Address, H256};
+use clap::{App, ArgMatches, load_yaml, value_t, values_t, arg_enum, _clap_count_exprs};
 use colored::Colorize;
 use failure::Error;
+use crate::err::ErrorKind;
 use super::{
     LogLevel,
-    config_file::{ConfigFile, Transport},
+    config_file::{ConfigFile, Transport, Explorer},
 };
 
+arg_enum! { // places the validator looks for transactions of an address
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Source {
+        EtherScan,
+        Traces,
+        Logs
+    }
+}
+
+arg_enum! { // what to do with data fetched for a transaction that is already cached
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Duplicates {
+        Keep,
+        Overwrite,
+        Verify
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Action {
-    Validate{file: PathBuf, to: Option<u64>, address: Address},
+    /// `token_transfers`: whether addresses that appear only in token transfers are looked for
+    /// `sources`: where transactions of the address are looked for, along with `hash_file` if one is given
+    /// `duplicates`: what to do with data fetched for a transaction that is already cached
+    Validate{file: PathBuf, from: Option<u64>, to: Option<u64>, address: Address, token_transfers: bool, sources: Vec<Source>, hash_file: Option<PathBuf>, duplicates: Duplicates},
+    Cache(CacheCommand),
+}
+
+impl Action {
+    /// whether the action talks to an Ethereum node
+    pub fn needs_node(&self) -> bool {
+        match self {
+            Action::Validate{..} => true,
+            Action::Cache(_) => false,
+        }
+    }
+}
+
+/// `absentis cache` subcommands. These only touch the local cache.
+/// `chain` restricts a command to the cache of one chain; commands that read a single cache default to mainnet
+#[derive(Debug, Clone)]
+pub enum CacheCommand {
+    List{chain: Option<u64>},
+    Inspect{chain: Option<u64>, address: Address, transaction: TxRef},
+    Verify{chain: Option<u64>, address: Option<Address>},
+    Purge{chain: Option<u64>, address: Option<Address>, older_than: Option<u64>},
+    Compact{chain: Option<u64>, address: Option<Address>},
+    Export{chain: Option<u64>, address: Address, format: ExportFormat, output: Option<PathBuf>},
+}
+
+/// a transaction, by hash or by `block:index`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxRef {
+    Hash(H256),
+    Position(u64, usize),
+}
+
+impl FromStr for TxRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TxRef, Error> {
+        if let Some(pos) = s.find(':') {
+            let block = s[..pos].parse().map_err(|_| ErrorKind::CLI)?;
+            let index = s[pos + 1..].parse().map_err(|_| ErrorKind::CLI)?;
+            Ok(TxRef::Position(block, index))
+        } else {
+            Ok(TxRef::Hash(H256::from_str(strip_hex(s)).map_err(|_| ErrorKind::CLI)?))
+        }
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ExportFormat {
+        Json,
+        Csv
+    }
 }
 
 pub struct CLIArgs {
@@ -20,6 +94,8 @@ pub struct CLIArgs {
     pub url: Option<String>,
     pub transport: Option<Transport>,
     pub log_level: LogLevel,
+    pub etherscan_key: Option<String>,
+    pub explorer: Option<Explorer>,
     pub action: Action,
 }
 
@@ -81,15 +157,29 @@ pub fn parse() -> Result<CLIArgs, Error> {
         transport = Some(transport.expect("transport is conditional on node; qed"));
     }
 
-    if file.is_none() && url.is_none() {
+    let cache = matches.subcommand_matches("cache");
+    if file.is_none() && url.is_none() && cache.is_none() {
         error!("Need a configuration file or a node url/w transport specified to proceed");
         std::process::exit(1);
     }
     let mut action = None;
-    if let Some(file) = matches.value_of("csv_validate") {
+    if let Some(cache) = cache {
+        action = Some(Action::Cache(cache_command(cache)?));
+    } else if let Some(file) = matches.value_of("csv_validate") {
+        let from = matches.value_of("from").map(|f| f.parse()).transpose()?;
         let to = matches.value_of("to").map(|t| t.parse()).transpose()?;
         let address = matches.value_of("address").unwrap();
-        action = Some(Action::Validate{file: PathBuf::from(file), to: to, address: Address::from_str(address)?})
+        let token_transfers = !matches.is_present("no_token_transfers");
+        let hash_file = matches.value_of("hash_file").map(PathBuf::from);
+        let mut sources = values_t!(matches.values_of("source"), Source).unwrap_or_else(|_| Vec::new());
+        let duplicates = value_t!(matches.value_of("on_duplicate"), Duplicates).unwrap_or(Duplicates::Verify);
+        // a hash file alone is enough to validate without an explorer
+        if sources.is_empty() && hash_file.is_none() {
+            sources.push(Source::EtherScan);
+        }
+        action = Some(Action::Validate{
+            file: PathBuf::from(file), from, to, address: Address::from_str(address)?, token_transfers, sources, hash_file, duplicates
+        })
     }
     // possible options:
     // Url + Transport
@@ -103,8 +193,68 @@ pub fn parse() -> Result<CLIArgs, Error> {
         std::process::exit(1);
     }
     let action = action.unwrap();
+    let etherscan_key = matches.value_of("etherscan_key").map(|k| k.to_owned());
+    let explorer = value_t!(matches.value_of("explorer"), Explorer).ok();
 
     Ok(CLIArgs {
-        file, url, transport, log_level, action
+        file, url, transport, log_level, etherscan_key, explorer, action
     })
 }
+
+fn cache_command(matches: &ArgMatches) -> Result<CacheCommand, Error> {
+    let address = |m: &ArgMatches| -> Result<Option<Address>, Error> {
+        Ok(m.value_of("address").map(|a| Address::from_str(strip_hex(a))).transpose()?)
+    };
+    // `--chain` is global, so it may be given before or after the subcommand
+    let chain = |m: &ArgMatches| -> Result<Option<u64>, Error> {
+        Ok(m.value_of("chain").or(matches.value_of("chain")).map(|c| c.parse()).transpose()?)
+    };
+    match matches.subcommand() {
+        ("list", Some(m)) => Ok(CacheCommand::List { chain: chain(m)? }),
+        ("inspect", Some(m)) => Ok(CacheCommand::Inspect {
+            chain: chain(m)?,
+            address: address(m)?.expect("address is required; qed"),
+            transaction: m.value_of("transaction").expect("transaction is required; qed").parse()?,
+        }),
+        ("verify", Some(m)) => Ok(CacheCommand::Verify { chain: chain(m)?, address: address(m)? }),
+        ("purge", Some(m)) => {
+            let older_than = m.value_of("older_than").map(|d| d.parse()).transpose()?;
+            let address = address(m)?;
+            if address.is_none() && older_than.is_none() {
+                error!("Need to specify an address or an age to purge");
+                std::process::exit(1);
+            }
+            Ok(CacheCommand::Purge { chain: chain(m)?, address, older_than })
+        },
+        ("compact", Some(m)) => Ok(CacheCommand::Compact { chain: chain(m)?, address: address(m)? }),
+        ("export", Some(m)) => Ok(CacheCommand::Export {
+            chain: chain(m)?,
+            address: address(m)?.expect("address is required; qed"),
+            format: value_t!(m.value_of("format"), ExportFormat).unwrap_or(ExportFormat::Json),
+            output: m.value_of("output").map(PathBuf::from),
+        }),
+        _ => {
+            error!("Need to specify a cache command");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// hex may be given with or without a `0x` prefix
+fn strip_hex(s: &str) -> &str {
+    if s.starts_with("0x") { &s[2..] } else { s }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_tx_refs() {
+        assert_eq!(TxRef::from_str("1165405:2").unwrap(), TxRef::Position(1165405, 2));
+        let hash = "0x6f9e6ba0e3e8b1d3c7bfbd8d6b0b0d2a43cf5a0aa5ff4fb4a1aa5e2e80f5a3a1";
+        assert_eq!(TxRef::from_str(hash).unwrap(), TxRef::Hash(H256::from_str(&hash[2..]).unwrap()));
+        assert!(TxRef::from_str("1165405:").is_err());
+        assert!(TxRef::from_str("0xnothex").is_err());
+    }
+}
@@ -14,6 +14,38 @@ pub struct ConfigFile {
     default: String, // identifier for default node to use
     nodes: Option<Vec<EthNode>>,
     infura: Option<Infura>,
+    #[serde(default)]
+    etherscan: Option<EtherScanConf>,
+    /// explorer profiles of other networks
+    #[serde(default)]
+    explorers: Option<Vec<EtherScanConf>>,
+}
+
+/// the `[etherscan]` section, or an entry of `[[explorers]]`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EtherScanConf {
+    /// the chain this profile is for. `[etherscan]` is used for every chain without a profile if it is not set
+    pub chain_id: Option<u64>,
+    /// defaults to EtherScan
+    pub explorer: Option<Explorer>,
+    /// defaults to the API of `explorer` on the chain, for well-known chains
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    /// defaults to what the explorer allows with or without an API key
+    pub requests_per_second: Option<f64>,
+}
+
+arg_enum! { // the API a block explorer speaks
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    pub enum Explorer {
+        EtherScan,
+        BlockScout
+    }
+}
+impl Default for Explorer {
+    fn default() -> Explorer {
+        Explorer::EtherScan
+    }
 }
 
 arg_enum! { // allows for automatic deser of cli args into enum
@@ -104,9 +136,19 @@ impl Default for ConfigFile {
             api_key: "".to_string(),
         });
 
+        let etherscan = Some(EtherScanConf {
+            api_key: Some("".to_string()),
+            chain_id: None,
+            explorer: None,
+            url: None,
+            requests_per_second: None,
+        });
+
         ConfigFile {
             nodes: Some(nodes),
+            explorers: None,
             infura,
+            etherscan,
             default: "Parity".to_string()
         }
     }
@@ -217,6 +259,16 @@ impl ConfigFile {
         Ok(inf.api_key.clone())
     }
 
+    /// the `[etherscan]` section, if it is set
+    pub fn etherscan(&self) -> Option<&EtherScanConf> {
+        self.etherscan.as_ref()
+    }
+
+    /// the `[[explorers]]` profiles
+    pub fn explorers(&self) -> &[EtherScanConf] {
+        self.explorers.as_ref().map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
     pub fn default_ident(&self) -> &String { // the default node
         &self.default
     }
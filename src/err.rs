@@ -20,8 +20,14 @@ pub enum ErrorKind {
     InvalidConfiguration(ConfMsg),
     #[fail(display = "Error while attempting to load transactions from database")]
     Database,
+    #[fail(display = "Database {} is corrupt; remove it to rebuild the cache", _0)]
+    CorruptDatabase(String),
     #[fail(display = "Error interacting with cache")]
     Cache,
+    #[fail(display = "Cache was fetched from chain {}, but the node is on chain {}", _0, _1)]
+    WrongChain(u64, u64),
+    #[fail(display = "Interrupted; progress was saved, and the next run will resume from it")]
+    Interrupted,
     #[fail(display = "Error validating transactions")]
     Validate(ValidateMsg),
     #[fail(display = "An Internal Error has occurred. Please File a Bug Report.")]
@@ -31,7 +37,11 @@ pub enum ErrorKind {
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
 pub enum ValidateMsg {
     #[fail(display = "CSV could not be parsed; invalid data")]
-    InvalidCsv
+    InvalidCsv,
+    #[fail(display = "Unknown transaction location {}", _0)]
+    InvalidLocation(String),
+    #[fail(display = "Hash file line could not be parsed: {}", _0)]
+    InvalidHashFile(String),
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
@@ -41,7 +51,13 @@ pub enum ConfMsg {
     #[fail(display = "Option {} not set", _0)]
     OptionNotSet(String),
     #[fail(display = "Could not find {}", _0)]
-    NotFound(String)
+    NotFound(String),
+    #[fail(display = "Block {} to start from is past block {} to stop at", _0, _1)]
+    EmptyRange(u64, u64),
+    #[fail(display = "{} is not an http or https URL", _0)]
+    InvalidUrl(String),
+    #[fail(display = "requests_per_second must be a positive number, not {}", _0)]
+    InvalidRate(String),
 }
 
 impl Fail for AbsentisError {
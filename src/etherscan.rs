@@ -1,20 +1,59 @@
 use log::*;
-#[macro_use] mod types;
-pub use self::types::{EtherScanTx, EtherScanInternalTx, EtherScanResponse};
-use crate::err::{ErrorKind};
+mod types;
+mod limiter;
+mod err;
+mod request;
+pub use self::types::{
+    EtherScanTx, EtherScanInternalTx, EtherScanTokenTx, EtherScanNftTx, EtherScanMinedBlock, EtherScanRecord, EtherScanResponse,
+};
+pub use self::err::EtherScanError;
+pub use self::request::{EtherScanRequest, Module, Action, BlockType};
+use self::limiter::TokenBucket;
+use crate::{
+    err::ErrorKind,
+    conf::{Explorer, ExplorerConf},
+    types::ETHERSCAN_URL,
+};
 use hyper::client::HttpConnector;
-use itertools::Itertools;
+use hyper_tls::HttpsConnector;
 use futures::{
     future::Future,
     stream::Stream,
 };
 use failure::{Error, ResultExt};
-use web3::types::{H160, H256};
+use serde::de::DeserializeOwned;
+use std::{sync::Mutex, time::Duration};
+use web3::types::H160;
 
+/// EtherScan returns at most this many records for one request
+const MAX_RESULTS: usize = 10_000;
+/// records requested per page of an action that does not take a block range
+const PAGE_SIZE: usize = 1_000;
+/// requests per second EtherScan allows with an API key
+const KEYED_RATE: f64 = 5.0;
+/// requests per second EtherScan allows without an API key
+const ANONYMOUS_RATE: f64 = 0.2;
+/// requests per second sent to Blockscout, which does not take API keys
+const BLOCKSCOUT_RATE: f64 = 5.0;
+/// threads resolving the host of the explorer
+const DNS_THREADS: usize = 4;
+/// times a rate limited request is retried
+const MAX_RETRIES: u32 = 6;
+/// milliseconds to wait before the first retry of a rate limited request. Doubled for every retry after it
+const INITIAL_BACKOFF_MS: u64 = 500;
+
+/// A client of the EtherScan API, or of an explorer compatible with it
 pub struct EtherScan {
-    client: hyper::client::Client<HttpConnector, hyper::Body>,
+    client: hyper::client::Client<HttpsConnector<HttpConnector>, hyper::Body>,
+    explorer: Explorer,
+    url: String,
+    api_key: Option<String>,
+    limiter: Mutex<TokenBucket>,
+    /// whether ERC-20 and ERC-721 token transfers are listed with the transactions of an account
+    token_transfers: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SortType {
     Ascending,
     Descending,
@@ -25,7 +64,7 @@ impl From<&SortType> for String {
     fn from(sort_type: &SortType) -> String {
         match sort_type {
             SortType::Ascending => "asc".to_string(),
-            SortType::Descending => "des".to_string(),
+            SortType::Descending => "desc".to_string(),
             SortType::None => "asc".to_string()
         }
     }
@@ -33,46 +72,310 @@ impl From<&SortType> for String {
 
 
 impl EtherScan {
-    pub fn new() -> Self {
-        EtherScan {
-            client: hyper::client::Client::new()
+    /// requests per second default to what the explorer allows with or without an API key.
+    /// Explorers are reached over HTTPS, or plain HTTP if their URL says so
+    pub fn new(conf: &ExplorerConf) -> Result<Self, Error> {
+        let rate = conf.requests_per_second.unwrap_or(match (conf.explorer, conf.api_key.is_some()) {
+            (Explorer::BlockScout, _) => BLOCKSCOUT_RATE,
+            (Explorer::EtherScan, true) => KEYED_RATE,
+            (Explorer::EtherScan, false) => ANONYMOUS_RATE,
+        });
+        if conf.explorer == Explorer::EtherScan && conf.api_key.is_none() {
+            warn!("No EtherScan API key set; requests are limited to {} per second", rate);
         }
+        info!("Using the {} API at {}", conf.explorer, conf.url);
+        let https = HttpsConnector::new(DNS_THREADS).map_err(|e| ErrorKind::Network(format!("Could not set up TLS: {}", e)))?;
+        Ok(EtherScan {
+            client: hyper::client::Client::builder().build(https),
+            limiter: Mutex::new(TokenBucket::new(rate)),
+            token_transfers: true,
+            explorer: conf.explorer,
+            url: conf.url.clone(),
+            api_key: conf.api_key.clone(),
+        })
+    }
+
+    /// list token transfers with the transactions of an account. On by default
+    pub fn token_transfers(&mut self, val: bool) -> &mut Self {
+        let new = self;
+        new.token_transfers = val;
+        new
+    }
+
+    /// the API this explorer speaks
+    pub fn explorer(&self) -> Explorer {
+        self.explorer
     }
 
-    /// returns all hashes of transactions (external  + internal)
+    /// whether token transfers are listed with the transactions of an account
+    pub fn lists_token_transfers(&self) -> bool {
+        self.token_transfers
+    }
+
+    /// returns every record of transactions (external  + internal), and of token transfers if they are enabled.
+    /// A transaction may have several records
     pub fn get_tx_by_account(&self, ev_loop: &mut tokio_core::reactor::Core,
                              addr: H160,
                              from: u64,
                              to: u64,
                              sort: SortType)
-                             -> Result<Vec<(H256, u64)>, Error>
+                             -> Result<Vec<EtherScanRecord>, Error>
+    {
+        let mut txlist = self.request(Module::Account, Action::TxList);
+        txlist.address(addr).sort(sort);
+        let mut internal = self.request(Module::Account, Action::TxListInternal);
+        internal.address(addr).sort(sort);
+        let norm_response = self.get_all::<EtherScanTx>(ev_loop, from, to, &txlist)?;
+        let int_response = self.get_all::<EtherScanInternalTx>(ev_loop, from, to, &internal)?;
+        let mut records = norm_response
+            .into_iter()
+            .map(EtherScanRecord::Normal)
+            .chain(int_response.into_iter().map(EtherScanRecord::Internal))
+            .collect::<Vec<EtherScanRecord>>();
+        if self.token_transfers {
+            let mut tokens = self.request(Module::Account, Action::TokenTx);
+            tokens.address(addr).sort(sort);
+            let token_response = self.get_all::<EtherScanTokenTx>(ev_loop, from, to, &tokens)?;
+            info!("{} token transfers", token_response.len());
+            records.extend(token_response.into_iter().map(EtherScanRecord::Token));
+            if self.explorer == Explorer::BlockScout {
+                // ERC-721 transfers are listed by `tokentx`
+                warn!("Blockscout does not have `tokennfttx`; NFT transfers are only found if `tokentx` lists them");
+            } else {
+                let mut nfts = self.request(Module::Account, Action::TokenNftTx);
+                nfts.address(addr).sort(sort);
+                let nft_response = self.get_all::<EtherScanNftTx>(ev_loop, from, to, &nfts)?;
+                info!("{} NFT transfers", nft_response.len());
+                records.extend(nft_response.into_iter().map(EtherScanRecord::Nft));
+            }
+        }
+        Ok(records)
+    }
+
+    /// every block and uncle `addr` mined in blocks `from..=to`, with the `BlockType` it was listed as
+    pub fn get_mined_blocks(&self, ev_loop: &mut tokio_core::reactor::Core,
+                            addr: H160,
+                            from: u64,
+                            to: u64)
+                            -> Result<Vec<(u64, BlockType)>, Error>
     {
-        let req_normtx = eth_txlist!(addr, from.to_string(), to.to_string(), String::from(&sort));
-        let req_inttx = eth_int_txlist!(addr, from.to_string(), to.to_string(), String::from(&sort));
-        info!("URL: {}::::{}", req_normtx, req_inttx);
-        let normal =  ev_loop.run(self.do_get(req_normtx.parse().expect("URI should not be invalid; qed")))?;
-        let internal = ev_loop.run(self.do_get(req_inttx.parse().expect("URI should not be invalid; qed")))?;
-        let norm_response = serde_json::from_slice::<EtherScanResponse<Vec<EtherScanTx>>>(&normal.to_vec())?.result;
-        let int_response = serde_json::from_slice::<EtherScanResponse<Vec<EtherScanInternalTx>>>(&internal.to_vec()).context(ErrorKind::Parse)?.result;
-        Ok(norm_response
-           .iter()
-           .map(|x| (x.hash, x.block_number))
-           .chain(int_response.iter().map(|x| (x.hash, x.block_number)))
-           .into_iter()
-           .unique_by(|x| x.0)
-           .collect::<Vec<(H256, u64)>>())
-    }
-
-    fn do_get(&self, uri: hyper::Uri) -> impl Future<Item = bytes::Bytes, Error = Error> {
+        let mut mined = Vec::new();
+        let block_types: &[BlockType] = match self.explorer {
+            Explorer::EtherScan => &[BlockType::Blocks, BlockType::Uncles],
+            Explorer::BlockScout => {
+                warn!("Blockscout does not list mined uncles; uncle rewards are only checked against the node");
+                &[BlockType::Blocks]
+            },
+        };
+        for block_type in block_types {
+            let mut request = self.request(Module::Account, Action::GetMinedBlocks);
+            request.address(addr);
+            // Blockscout only lists blocks, and does not take a `blocktype`
+            if self.explorer == Explorer::EtherScan {
+                request.block_type(*block_type);
+            }
+            // `getminedblocks` does not take a block range
+            let blocks = self.get_pages::<EtherScanMinedBlock>(ev_loop, &request)?;
+            mined.extend(blocks.into_iter()
+                .filter(|b| b.block_number >= from && b.block_number <= to)
+                .map(|b| (b.block_number, *block_type)));
+        }
+        info!("{} mined blocks and uncles in blocks {}..{}", mined.len(), from, to);
+        Ok(mined)
+    }
+
+    /// a request to this explorer, with its API key
+    pub fn request(&self, module: Module, action: Action) -> EtherScanRequest {
+        let mut request = EtherScanRequest::new(module, action);
+        request.base_url(&self.url).api_key(self.api_key.clone());
+        request
+    }
+
+    /// every record of `request` in blocks `from..=to`.
+    /// A response with `MAX_RESULTS` records may have been cut off, so its block range is split in half
+    /// and each half requested on its own, until no response is full. Records are returned in block order
+    fn get_all<T>(&self, ev_loop: &mut tokio_core::reactor::Core,
+                  from: u64,
+                  to: u64,
+                  request: &EtherScanRequest)
+                  -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug
+    {
+        let mut records = Vec::new();
+        // a stack, so that the lower half of a split range is requested first
+        let mut ranges = vec![(from, to)];
+        while let Some((from, to)) = ranges.pop() {
+            info!("Requesting {} of blocks {}..{} from EtherScan", request.action(), from, to);
+            let url = request.clone().start_block(from).end_block(to).url();
+            let response = self.records::<T>(ev_loop, url)?;
+            if response.len() < MAX_RESULTS {
+                records.extend(response);
+            } else if from == to {
+                warn!("Block {} has {} or more records; EtherScan may have left some out", from, MAX_RESULTS);
+                records.extend(response);
+            } else {
+                let mid = from + (to - from) / 2;
+                info!("EtherScan returned a full page for blocks {}..{}; splitting into {}..{} and {}..{}",
+                      from, to, from, mid, mid + 1, to);
+                ranges.push((mid + 1, to));
+                ranges.push((from, mid));
+            }
+        }
+        Ok(records)
+    }
+
+    /// every record of `request`, requested `PAGE_SIZE` records at a time
+    fn get_pages<T>(&self, ev_loop: &mut tokio_core::reactor::Core, request: &EtherScanRequest) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + std::fmt::Debug
+    {
+        let mut records = Vec::new();
+        for page in 1.. {
+            info!("Requesting page {} of {} from EtherScan", page, request.action());
+            let url = request.clone().page(page).offset(PAGE_SIZE as u64).url();
+            let response = self.records::<T>(ev_loop, url)?;
+            let full = response.len() == PAGE_SIZE;
+            records.extend(response);
+            if !full {
+                break;
+            }
+        }
+        Ok(records)
+    }
+
+    /// the records of the response to `url`. EtherScan reports an empty list as a failure
+    fn records<T>(&self, ev_loop: &mut tokio_core::reactor::Core, url: String) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned
+    {
+        match self.fetch(ev_loop, url) {
+            Ok(result) => {
+                let result = match self.explorer {
+                    Explorer::EtherScan => result,
+                    Explorer::BlockScout => blockscout_records(result),
+                };
+                Ok(serde_json::from_value(result).context(ErrorKind::Parse)?)
+            },
+            Err(EtherScanError::Empty) => Ok(Vec::new()),
+            Err(e) => Err(e.into_network()),
+        }
+    }
+
+    /// GET `url` once the rate limiter allows it, retrying with exponential backoff while EtherScan reports
+    /// that its rate limit was reached. Returns the `result` of the response
+    fn fetch(&self, ev_loop: &mut tokio_core::reactor::Core, url: String) -> Result<serde_json::Value, EtherScanError> {
+        // the base URL is validated by `Configuration::explorer`, and the query is percent-encoded
+        let uri: hyper::Uri = url.parse().map_err(|e| EtherScanError::Http(format!("Invalid request URL: {}", e)))?;
+        let mut backoff = Duration::from_millis(INITIAL_BACKOFF_MS);
+        for retry in 0..=MAX_RETRIES {
+            if retry > 0 {
+                warn!("EtherScan rate limit reached; retrying in {:?}", backoff);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            self.limiter.lock().expect("limiter is never held across a panic; qed").wait();
+            let response = ev_loop.run(self.do_get(uri.clone()))?;
+            match result(&response) {
+                Err(EtherScanError::RateLimited) => continue,
+                res => return res,
+            }
+        }
+        error!("EtherScan rate limit still reached after {} retries", MAX_RETRIES);
+        Err(EtherScanError::RateLimited)
+    }
+
+    fn do_get(&self, uri: hyper::Uri) -> impl Future<Item = bytes::Bytes, Error = EtherScanError> {
         self.client
             .get(uri)
+            .map_err(|e| EtherScanError::Http(format!("{}", e)))
             .and_then(|res| {
-                assert_eq!(res.status(), hyper::StatusCode::OK);
-                res.into_body().concat2()
-            })
-            .map_err(|e| ErrorKind::Network(format!("{}", e)).into())
-            .and_then(|json| {
-                futures::future::result(Ok(json.into_bytes()))
+                if res.status().is_success() {
+                    Ok(res)
+                } else {
+                    Err(EtherScanError::Http(format!("{}", res.status())))
+                }
             })
+            .and_then(|res| res.into_body().concat2().map_err(|e| EtherScanError::Http(format!("{}", e))))
+            .map(|json| json.into_bytes())
+    }
+}
+
+/// the API of `explorer` on chain `chain_id`, if it is a well-known chain
+pub fn default_url(explorer: Explorer, chain_id: u64) -> Option<&'static str> {
+    let url = match (explorer, chain_id) {
+        (Explorer::EtherScan, 1) => ETHERSCAN_URL,
+        (Explorer::EtherScan, 3) => "https://api-ropsten.etherscan.io/api",
+        (Explorer::EtherScan, 4) => "https://api-rinkeby.etherscan.io/api",
+        (Explorer::EtherScan, 42) => "https://api-kovan.etherscan.io/api",
+        (Explorer::BlockScout, 1) => "https://blockscout.com/eth/mainnet/api",
+        (Explorer::BlockScout, 3) => "https://blockscout.com/eth/ropsten/api",
+        (Explorer::BlockScout, 42) => "https://blockscout.com/eth/kovan/api",
+        (Explorer::BlockScout, 61) => "https://blockscout.com/etc/mainnet/api",
+        (Explorer::BlockScout, 99) => "https://blockscout.com/poa/core/api",
+        (Explorer::BlockScout, 100) => "https://blockscout.com/poa/dai/api",
+        _ => return None,
+    };
+    Some(url)
+}
+
+/// records of a Blockscout response, in the form EtherScan writes them.
+/// Blockscout writes missing values as `null`, and the hash of an internal transaction as `transactionHash`
+fn blockscout_records(result: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match result {
+        Value::Array(records) => Value::Array(records.into_iter().map(|record| match record {
+            Value::Object(mut fields) => {
+                if let Some(hash) = fields.remove("transactionHash") {
+                    fields.entry("hash").or_insert(hash);
+                }
+                for val in fields.values_mut().filter(|v| v.is_null()) {
+                    *val = Value::String(String::new());
+                }
+                Value::Object(fields)
+            },
+            other => other,
+        }).collect()),
+        other => other,
+    }
+}
+
+/// the `result` of an EtherScan response, or what went wrong.
+/// EtherScan reports failures with a status of 0, and a message in place of the result
+fn result(response: &[u8]) -> Result<serde_json::Value, EtherScanError> {
+    let response = serde_json::from_slice::<EtherScanResponse<serde_json::Value>>(response)
+        .map_err(|e| EtherScanError::Malformed(format!("{}", e)))?;
+    if response.status == 1 {
+        return Ok(response.result);
+    }
+    match response.result {
+        serde_json::Value::Array(ref records) if records.is_empty() => Err(EtherScanError::Empty),
+        // Blockscout has no result at all
+        serde_json::Value::Null if response.message.starts_with("No ") => Err(EtherScanError::Empty),
+        serde_json::Value::String(ref msg) if msg.contains("rate limit") => Err(EtherScanError::RateLimited),
+        serde_json::Value::String(msg) => Err(EtherScanError::Api { message: response.message, result: msg }),
+        other => Err(EtherScanError::Api { message: response.message, result: other.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_read_etherscan_responses() {
+        assert_eq!(result(br#"{"status":"1","message":"OK","result":[]}"#), Ok(serde_json::Value::Array(Vec::new())));
+        assert_eq!(result(br#"{"status":"0","message":"No transactions found","result":[]}"#), Err(EtherScanError::Empty));
+        assert_eq!(result(br#"{"status":"0","message":"NOTOK","result":"Max rate limit reached"}"#), Err(EtherScanError::RateLimited));
+        assert_eq!(result(br#"{"status":"0","message":"NOTOK","result":"Invalid API Key"}"#),
+                   Err(EtherScanError::Api { message: "NOTOK".to_string(), result: "Invalid API Key".to_string() }));
+        assert!(match result(b"<html>") { Err(EtherScanError::Malformed(_)) => true, _ => false });
+        assert_eq!(result(br#"{"status":"0","message":"No internal transactions found","result":null}"#), Err(EtherScanError::Empty));
+    }
+
+    #[test]
+    fn it_should_read_blockscout_records() {
+        let records = serde_json::json!([{"transactionHash": "0x01", "to": null, "traceId": "0"}]);
+        assert_eq!(blockscout_records(records), serde_json::json!([{"hash": "0x01", "to": "", "traceId": "0"}]));
     }
 }
@@ -0,0 +1,24 @@
+use failure::{Error, Fail};
+use crate::err::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq, Fail)]
+pub enum EtherScanError {
+    #[fail(display = "HTTP request to EtherScan failed: {}", _0)]
+    Http(String),
+    #[fail(display = "EtherScan API error {}: {}", message, result)]
+    Api { message: String, result: String },
+    #[fail(display = "EtherScan found no records")]
+    Empty,
+    #[fail(display = "EtherScan rate limit reached")]
+    RateLimited,
+    #[fail(display = "EtherScan response could not be parsed: {}", _0)]
+    Malformed(String),
+}
+
+impl EtherScanError {
+    /// an `ErrorKind::Network` caused by this error
+    pub fn into_network(self) -> Error {
+        let msg = self.to_string();
+        self.context(ErrorKind::Network(msg)).into()
+    }
+}
@@ -0,0 +1,72 @@
+//! client-side rate limiting, so that requests stay under what EtherScan allows
+use std::time::{Duration, Instant};
+
+/// A token bucket: holds up to `capacity` tokens, refilled at `rate` tokens per second.
+/// Every request takes one token, and waits for one if the bucket is empty
+#[derive(Debug)]
+crate struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// tokens per second
+    rate: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// a full bucket allowing `rate` requests per second, and bursts of up to one second's worth of requests
+    crate fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket { capacity, tokens: capacity, rate, last: Instant::now() }
+    }
+
+    /// block until a token is available, and take it
+    crate fn wait(&mut self) {
+        if let Some(wait) = self.take(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// take a token at `now`, returning how long to wait before it may be used
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        if now > self.last {
+            let elapsed = now - self.last;
+            let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+            self.last = now;
+        }
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            None
+        } else {
+            let secs = -self.tokens / self.rate;
+            Some(Duration::new(secs as u64, (secs.fract() * 1e9) as u32))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_limit_requests() {
+        let mut bucket = TokenBucket::new(5.0);
+        let start = bucket.last;
+        for _ in 0..5 {
+            assert_eq!(bucket.take(start), None);
+        }
+        assert_eq!(bucket.take(start), Some(Duration::from_millis(200)));
+        // the debt of the request above is paid off 200ms later
+        let later = start + Duration::from_millis(400);
+        assert_eq!(bucket.take(later), None);
+        assert!(bucket.take(later).is_some());
+    }
+
+    #[test]
+    fn it_should_allow_rates_below_one_request_per_second() {
+        let mut bucket = TokenBucket::new(0.2);
+        let start = bucket.last;
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), Some(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,330 @@
+//! URLs of EtherScan API requests
+use web3::types::{Address, H256};
+use url::form_urlencoded;
+use crate::types::ETHERSCAN_URL;
+use super::SortType;
+
+/// a module of the EtherScan API
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Module {
+    Account,
+}
+
+impl std::fmt::Display for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Module::Account => write!(f, "account"),
+        }
+    }
+}
+
+/// an action of the `account` module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// ether balance of an address
+    Balance,
+    /// ether balance of up to 20 addresses
+    BalanceMulti,
+    /// ether balance of an address at a block
+    BalanceHistory,
+    /// balance an address holds of an ERC-20 token
+    TokenBalance,
+    /// normal transactions
+    TxList,
+    /// internal transactions
+    TxListInternal,
+    /// ERC-20 token transfers
+    TokenTx,
+    /// ERC-721 token transfers
+    TokenNftTx,
+    /// ERC-1155 token transfers
+    Token1155Tx,
+    /// blocks and uncles mined
+    GetMinedBlocks,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let action = match self {
+            Action::Balance => "balance",
+            Action::BalanceMulti => "balancemulti",
+            Action::BalanceHistory => "balancehistory",
+            Action::TokenBalance => "tokenbalance",
+            Action::TxList => "txlist",
+            Action::TxListInternal => "txlistinternal",
+            Action::TokenTx => "tokentx",
+            Action::TokenNftTx => "tokennfttx",
+            Action::Token1155Tx => "token1155tx",
+            Action::GetMinedBlocks => "getminedblocks",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// `blocktype` of `getminedblocks`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockType {
+    Blocks,
+    Uncles,
+}
+
+impl std::fmt::Display for BlockType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockType::Blocks => write!(f, "blocks"),
+            BlockType::Uncles => write!(f, "uncles"),
+        }
+    }
+}
+
+/// A request to the EtherScan API.
+/// Parameters that are not set are left out of the URL, so EtherScan uses its defaults for them
+#[derive(Debug, Clone)]
+pub struct EtherScanRequest {
+    base_url: String,
+    module: Module,
+    action: Action,
+    addresses: Vec<Address>,
+    contract_address: Option<Address>,
+    tx_hash: Option<H256>,
+    block_no: Option<u64>,
+    start_block: Option<u64>,
+    end_block: Option<u64>,
+    page: Option<u64>,
+    offset: Option<u64>,
+    sort: Option<SortType>,
+    block_type: Option<BlockType>,
+    tag: Option<String>,
+    api_key: Option<String>,
+}
+
+impl EtherScanRequest {
+    pub fn new(module: Module, action: Action) -> Self {
+        EtherScanRequest {
+            base_url: ETHERSCAN_URL.to_string(),
+            addresses: Vec::new(),
+            contract_address: None,
+            tx_hash: None,
+            block_no: None,
+            start_block: None,
+            end_block: None,
+            page: None,
+            offset: None,
+            sort: None,
+            block_type: None,
+            tag: None,
+            api_key: None,
+            module, action,
+        }
+    }
+
+    /// a request of the `account` module
+    pub fn account(action: Action) -> Self {
+        Self::new(Module::Account, action)
+    }
+
+    pub fn base_url(&mut self, val: &str) -> &mut Self {
+        let new = self;
+        new.base_url = val.to_string();
+        new
+    }
+    /// add an address. `balancemulti` takes several
+    pub fn address(&mut self, val: Address) -> &mut Self {
+        let new = self;
+        new.addresses.push(val);
+        new
+    }
+    /// the token of `tokenbalance`, or the token whose transfers are listed
+    pub fn contract_address(&mut self, val: Address) -> &mut Self {
+        let new = self;
+        new.contract_address = Some(val);
+        new
+    }
+    /// list the internal transactions of one transaction instead of an address
+    pub fn tx_hash(&mut self, val: H256) -> &mut Self {
+        let new = self;
+        new.tx_hash = Some(val);
+        new
+    }
+    /// block the balance of `balancehistory` is read at
+    pub fn block_no(&mut self, val: u64) -> &mut Self {
+        let new = self;
+        new.block_no = Some(val);
+        new
+    }
+    pub fn start_block(&mut self, val: u64) -> &mut Self {
+        let new = self;
+        new.start_block = Some(val);
+        new
+    }
+    pub fn end_block(&mut self, val: u64) -> &mut Self {
+        let new = self;
+        new.end_block = Some(val);
+        new
+    }
+    pub fn page(&mut self, val: u64) -> &mut Self {
+        let new = self;
+        new.page = Some(val);
+        new
+    }
+    /// records per page
+    pub fn offset(&mut self, val: u64) -> &mut Self {
+        let new = self;
+        new.offset = Some(val);
+        new
+    }
+    pub fn sort(&mut self, val: SortType) -> &mut Self {
+        let new = self;
+        new.sort = Some(val);
+        new
+    }
+    pub fn block_type(&mut self, val: BlockType) -> &mut Self {
+        let new = self;
+        new.block_type = Some(val);
+        new
+    }
+    /// block the balance is read at; `latest`, `earliest` or `pending`
+    pub fn tag(&mut self, val: &str) -> &mut Self {
+        let new = self;
+        new.tag = Some(val.to_string());
+        new
+    }
+    pub fn api_key(&mut self, val: Option<String>) -> &mut Self {
+        let new = self;
+        new.api_key = val;
+        new
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    /// the URL of this request
+    pub fn url(&self) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("module", &self.module.to_string());
+        query.append_pair("action", &self.action.to_string());
+        if !self.addresses.is_empty() {
+            let addresses = self.addresses.iter().map(|a| format!("0x{:x}", a)).collect::<Vec<String>>();
+            query.append_pair("address", &addresses.join(","));
+        }
+        if let Some(contract) = self.contract_address {
+            query.append_pair("contractaddress", &format!("0x{:x}", contract));
+        }
+        if let Some(hash) = self.tx_hash {
+            query.append_pair("txhash", &format!("0x{:x}", hash));
+        }
+        if let Some(block) = self.block_no {
+            query.append_pair("blockno", &block.to_string());
+        }
+        if let Some(start) = self.start_block {
+            query.append_pair("startblock", &start.to_string());
+        }
+        if let Some(end) = self.end_block {
+            query.append_pair("endblock", &end.to_string());
+        }
+        if let Some(page) = self.page {
+            query.append_pair("page", &page.to_string());
+        }
+        if let Some(offset) = self.offset {
+            query.append_pair("offset", &offset.to_string());
+        }
+        if let Some(sort) = self.sort.as_ref() {
+            query.append_pair("sort", &String::from(sort));
+        }
+        if let Some(block_type) = self.block_type {
+            query.append_pair("blocktype", &block_type.to_string());
+        }
+        if let Some(tag) = self.tag.as_ref() {
+            query.append_pair("tag", tag);
+        }
+        if let Some(key) = self.api_key.as_ref() {
+            query.append_pair("apikey", key);
+        }
+        // parameters are percent-encoded, so a key with reserved characters can not add parameters of its own
+        format!("{}?{}", self.base_url, query.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: &str = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+
+    #[test]
+    fn it_should_build_txlist_urls() {
+        let url = EtherScanRequest::account(Action::TxList)
+            .address(Address::from(ADDR))
+            .start_block(0)
+            .end_block(6_000_000)
+            .sort(SortType::Ascending)
+            .api_key(Some("KEY".to_string()))
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=txlist&address={}\
+                                 &startblock=0&endblock=6000000&sort=asc&apikey=KEY", ADDR));
+    }
+
+    #[test]
+    fn it_should_build_paged_urls() {
+        let url = EtherScanRequest::account(Action::TokenTx)
+            .base_url("http://localhost:4000/api")
+            .address(Address::from(ADDR))
+            .page(2)
+            .offset(100)
+            .sort(SortType::Descending)
+            .url();
+        assert_eq!(url, format!("http://localhost:4000/api?module=account&action=tokentx&address={}\
+                                 &page=2&offset=100&sort=desc", ADDR));
+    }
+
+    #[test]
+    fn it_should_build_other_account_urls() {
+        let url = EtherScanRequest::account(Action::BalanceMulti)
+            .address(Address::from(ADDR))
+            .address(Address::zero())
+            .tag("latest")
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=balancemulti\
+                                 &address={}%2C0x{:x}&tag=latest", ADDR, Address::zero()));
+
+        let url = EtherScanRequest::account(Action::GetMinedBlocks)
+            .address(Address::from(ADDR))
+            .block_type(BlockType::Uncles)
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=getminedblocks\
+                                 &address={}&blocktype=uncles", ADDR));
+
+        let url = EtherScanRequest::account(Action::TokenBalance)
+            .contract_address(Address::zero())
+            .address(Address::from(ADDR))
+            .tag("latest")
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=tokenbalance\
+                                 &address={}&contractaddress=0x{:x}&tag=latest", ADDR, Address::zero()));
+
+        let url = EtherScanRequest::account(Action::BalanceHistory)
+            .address(Address::from(ADDR))
+            .block_no(2_000_000)
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=balancehistory\
+                                 &address={}&blockno=2000000", ADDR));
+    }
+
+    #[test]
+    fn it_should_encode_parameters() {
+        let url = EtherScanRequest::account(Action::TxList)
+            .address(Address::from(ADDR))
+            .api_key(Some("KEY&page=9 #".to_string()))
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=txlist&address={}&apikey=KEY%26page%3D9+%23", ADDR));
+    }
+
+    #[test]
+    fn it_should_build_internal_urls_of_a_transaction() {
+        let url = EtherScanRequest::account(Action::TxListInternal)
+            .tx_hash(H256::from(1))
+            .url();
+        assert_eq!(url, format!("https://api.etherscan.io/api?module=account&action=txlistinternal&txhash=0x{:x}", H256::from(1)));
+    }
+}
@@ -1,136 +1,401 @@
 use serde_derive::*;
-use serde::de::{self, Deserialize, Deserializer};
+use serde::{
+    de::{self, Deserialize, Deserializer},
+    ser::Serializer,
+};
 use web3::types::{H256, U256, Bytes, Address};
-use std::fmt::Debug;
+use std::{fmt::Debug, str::FromStr};
+
+// Records are stored with the transactions they list, in the form EtherScan returns them,
+// so they read back the same way no matter the codec of the store
 
 // 60,137,282,256
-#[derive(Deserialize, PartialEq, Debug, Clone)]
+/// a normal transaction, from `txlist`
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EtherScanTx {
-    #[serde(rename = "blockNumber", deserialize_with = "from_str")]
+    #[serde(rename = "blockNumber", with = "string")]
     pub block_number: u64,
-    #[serde(rename = "timeStamp", deserialize_with = "from_str")]
+    #[serde(rename = "timeStamp", with = "string")]
     pub time_stamp: u64,
     pub hash: H256,
-    #[serde(deserialize_with = "from_str")]
+    #[serde(with = "string")]
     pub nonce: u64,
     #[serde(rename = "blockHash")]
     pub block_hash: H256,
-    #[serde(rename = "transactionIndex", deserialize_with="from_str")]
+    #[serde(rename = "transactionIndex", with = "string")]
     pub transaction_index: usize,
     pub from: Address,
-    pub to: String,
-    #[serde(deserialize_with="from_str")]
+    /// `None` for contract creations
+    #[serde(with = "address_or_empty")]
+    pub to: Option<Address>,
+    #[serde(with = "dec")]
     pub value: U256,
-    #[serde(deserialize_with="from_str")]
+    #[serde(with = "dec")]
     pub gas: U256,
-    #[serde(rename = "gasPrice", deserialize_with="from_str")]
+    #[serde(rename = "gasPrice", with = "dec")]
     pub gas_price: U256,
-    #[serde(default)]
-    pub is_error: String,
-    pub txreceipt_status: String, // no rename needed
+    #[serde(rename = "isError", with = "flag")]
+    pub is_error: bool,
+    /// `None` before Byzantium, when receipts had no status
+    #[serde(with = "flag_or_empty")]
+    pub txreceipt_status: Option<bool>,
     pub input: Bytes,
-    #[serde(rename = "contractAddress")]
-    pub contract_address: String,
-    #[serde(default, rename = "cumulative_gas_used", deserialize_with="from_str")]
-    pub cumulative_gas_used: u64,
-    #[serde(rename = "gasUsed", deserialize_with="from_str")]
-    pub gas_used: u64,
-    #[serde(deserialize_with="from_str")]
+    /// the contract created by the transaction
+    #[serde(rename = "contractAddress", with = "address_or_empty")]
+    pub contract_address: Option<Address>,
+    #[serde(rename = "cumulativeGasUsed", with = "dec")]
+    pub cumulative_gas_used: U256,
+    #[serde(rename = "gasUsed", with = "dec")]
+    pub gas_used: U256,
+    /// grows with every block, so it is left out of equality
+    #[serde(with = "string")]
     pub confirmations: u64,
 }
 
-#[derive(Deserialize, Debug)]
+impl PartialEq for EtherScanTx {
+    fn eq(&self, other: &EtherScanTx) -> bool {
+        self.block_number == other.block_number
+            && self.time_stamp == other.time_stamp
+            && self.hash == other.hash
+            && self.nonce == other.nonce
+            && self.block_hash == other.block_hash
+            && self.transaction_index == other.transaction_index
+            && self.from == other.from
+            && self.to == other.to
+            && self.value == other.value
+            && self.gas == other.gas
+            && self.gas_price == other.gas_price
+            && self.is_error == other.is_error
+            && self.txreceipt_status == other.txreceipt_status
+            && self.input == other.input
+            && self.contract_address == other.contract_address
+            && self.cumulative_gas_used == other.cumulative_gas_used
+            && self.gas_used == other.gas_used
+    }
+}
+
+/// a transaction internal to a contract call, from `txlistinternal`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct EtherScanInternalTx {
-    #[serde(rename = "blockNumber", deserialize_with="from_str")]
+    #[serde(rename = "blockNumber", with = "string")]
     pub block_number: u64,
-    #[serde(rename = "timeStamp")]
-    pub time_stamp: String,
-    pub hash: H256, // we only care about this one
-    pub from: String,
-    pub to: String,
-    pub value: String,
-    #[serde(rename = "contractAddress")]
-    pub contract_address: String,
+    #[serde(rename = "timeStamp", with = "string")]
+    pub time_stamp: u64,
+    pub hash: H256,
+    pub from: Address,
+    /// `None` for contract creations
+    #[serde(with = "address_or_empty")]
+    pub to: Option<Address>,
+    #[serde(with = "dec")]
+    pub value: U256,
+    /// the contract created by the transaction
+    #[serde(rename = "contractAddress", with = "address_or_empty")]
+    pub contract_address: Option<Address>,
+    /// EtherScan leaves this empty
+    #[serde(default)]
     pub input: String,
+    /// `call`, `create` or `suicide`
     #[serde(rename = "type")]
     pub tx_type: String,
-    pub gas: String,
-    #[serde(rename = "gasUsed")]
-    pub gas_used: String,
-    #[serde(rename = "traceId")]
+    #[serde(with = "dec")]
+    pub gas: U256,
+    #[serde(rename = "gasUsed", with = "dec")]
+    pub gas_used: U256,
+    /// not listed by Blockscout
+    #[serde(rename = "traceId", default)]
     pub trace_id: String,
-    #[serde(rename="isError")]
-    pub is_error: String,
-    #[serde(rename = "errCode")]
+    #[serde(rename = "isError", with = "flag")]
+    pub is_error: bool,
+    #[serde(rename = "errCode", default)]
     pub err_code: String
 }
 
+/// an ERC-20 token transfer, from `tokentx`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EtherScanTokenTx {
+    #[serde(rename = "blockNumber", with = "string")]
+    pub block_number: u64,
+    #[serde(rename = "timeStamp", with = "string")]
+    pub time_stamp: u64,
+    pub hash: H256,
+    #[serde(rename = "blockHash")]
+    pub block_hash: H256,
+    #[serde(rename = "transactionIndex", with = "string")]
+    pub transaction_index: usize,
+    pub from: Address,
+    pub to: Address,
+    /// the token contract
+    #[serde(rename = "contractAddress")]
+    pub contract_address: Address,
+    #[serde(with = "dec")]
+    pub value: U256,
+    #[serde(rename = "tokenName")]
+    pub token_name: String,
+    #[serde(rename = "tokenSymbol")]
+    pub token_symbol: String,
+    #[serde(rename = "tokenDecimal")]
+    pub token_decimal: String,
+}
+
+/// an ERC-721 token transfer, from `tokennfttx`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EtherScanNftTx {
+    #[serde(rename = "blockNumber", with = "string")]
+    pub block_number: u64,
+    #[serde(rename = "timeStamp", with = "string")]
+    pub time_stamp: u64,
+    pub hash: H256,
+    #[serde(rename = "blockHash")]
+    pub block_hash: H256,
+    #[serde(rename = "transactionIndex", with = "string")]
+    pub transaction_index: usize,
+    pub from: Address,
+    pub to: Address,
+    /// the token contract
+    #[serde(rename = "contractAddress")]
+    pub contract_address: Address,
+    #[serde(rename = "tokenID", with = "dec")]
+    pub token_id: U256,
+    #[serde(rename = "tokenName")]
+    pub token_name: String,
+    #[serde(rename = "tokenSymbol")]
+    pub token_symbol: String,
+}
+
+/// a block or uncle mined by an address, from `getminedblocks`
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct EtherScanMinedBlock {
+    #[serde(rename = "blockNumber", deserialize_with = "string::deserialize")]
+    pub block_number: u64,
+    #[serde(rename = "timeStamp", deserialize_with = "string::deserialize")]
+    pub time_stamp: u64,
+    #[serde(rename = "blockReward", deserialize_with = "dec::deserialize")]
+    pub block_reward: U256,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct EtherScanResponse<T: Debug> {
-    #[serde(deserialize_with = "from_str")]
-    status: i32,
-    message: String,
+    #[serde(deserialize_with = "string::deserialize")]
+    pub status: i32,
+    pub message: String,
     pub result: T,
 }
 
-fn from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
-where
-    T: std::str::FromStr,
-    T::Err: std::fmt::Display,
-    D: Deserializer<'de>
-{
-    let s = String::deserialize(deserializer)?;
-    T::from_str(&s).map_err(de::Error::custom)
-}
-// TODO: #p1 these should be functions, but i'm lazy
-#[macro_export]
-macro_rules! eth_txlist {
-    ($addr:expr, $from:expr, $to:expr) => ({
-        use crate::types::ETHERSCAN_URL;
-        info!("{:x}", $addr)
-        &format!("{}?module=account&action=txlist&address=0x{:x}&startblock={}&endblock={}&sort=asc",
-                ETHERSCAN_URL,
-                $addr,
-                $from,
-                $to
-        )
-    });
-    ($addr:expr, $from:expr, $to:expr, $sort:expr) => ({
-        use crate::types::ETHERSCAN_URL;
-        let addr = String::from(serde_json::to_string(&$addr)?);
-        info!("ADDR: {}", addr);
-        &format!("{}?module=account&action=txlist&address=0x{:x}&startblock={}&endblock={}&sort={}",
-                ETHERSCAN_URL,
-                $addr,
-                $from,
-                $to,
-                $sort
-        )
-    });
-}
-
-#[macro_export]
-macro_rules! eth_int_txlist {
-    ($addr:expr, $from:expr, $to:expr) => ({
-        use crate::types::ETHERSCAN_URL;
-        info!("{:x}", $addr)
-            &format!("{}?module=account&action=txlistinternal&address=0x{:x}&startblock={}&endblock={}&sort=asc",
-                     ETHERSCAN_URL,
-                     $addr,
-                     $from,
-                     $to
-            )
-    });
-    ($addr:expr, $from:expr, $to:expr, $sort:expr) => ({
-        use crate::types::ETHERSCAN_URL;
-        let addr = String::from(serde_json::to_string(&$addr)?);
-        info!("ADDR: {}", addr);
-        &format!("{}?module=account&action=txlistinternal&address=0x{:x}&startblock={}&endblock={}&sort={}",
-                 ETHERSCAN_URL,
-                 $addr,
-                 $from,
-                 $to,
-                 $sort
-        )
-    });
+/// any of the records of a transaction EtherScan lists for an address
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum EtherScanRecord {
+    Normal(EtherScanTx),
+    Internal(EtherScanInternalTx),
+    Token(EtherScanTokenTx),
+    Nft(EtherScanNftTx),
+}
+
+impl EtherScanRecord {
+    /// hash of the transaction this record is part of
+    pub fn hash(&self) -> H256 {
+        match self {
+            EtherScanRecord::Normal(tx) => tx.hash,
+            EtherScanRecord::Internal(tx) => tx.hash,
+            EtherScanRecord::Token(tx) => tx.hash,
+            EtherScanRecord::Nft(tx) => tx.hash,
+        }
+    }
+
+    pub fn block_number(&self) -> u64 {
+        match self {
+            EtherScanRecord::Normal(tx) => tx.block_number,
+            EtherScanRecord::Internal(tx) => tx.block_number,
+            EtherScanRecord::Token(tx) => tx.block_number,
+            EtherScanRecord::Nft(tx) => tx.block_number,
+        }
+    }
+}
+
+/// a value EtherScan writes as a string, like block numbers
+mod string {
+    use super::*;
+
+    pub fn serialize<T, S>(val: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: std::fmt::Display,
+        S: Serializer
+    {
+        serializer.serialize_str(&val.to_string())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+        D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        T::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// a U256 written in decimal. `U256::from_str` reads hex
+mod dec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(val: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_dec(*val))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_dec(&s).ok_or_else(|| de::Error::custom(format!("{} is not a 256-bit decimal number", s)))
+    }
+}
+
+/// `"0"` or `"1"`
+mod flag {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(val: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if *val { "1" } else { "0" })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_flag(&s).ok_or_else(|| de::Error::custom(format!("{} is not a flag", s)))
+    }
+}
+
+/// `"0"`, `"1"`, or empty if the value is unknown
+mod flag_or_empty {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(val: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error> {
+        match val {
+            Some(v) => flag::serialize(v, serializer),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.is_empty() {
+            return Ok(None);
+        }
+        parse_flag(&s).map(Some).ok_or_else(|| de::Error::custom(format!("{} is not a flag", s)))
+    }
+}
+
+/// an address, or empty if there is none
+mod address_or_empty {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(val: &Option<Address>, serializer: S) -> Result<S::Ok, S::Error> {
+        match val {
+            Some(addr) => serializer.serialize_str(&format!("0x{:x}", addr)),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Address>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.is_empty() {
+            return Ok(None);
+        }
+        let hex = if s.starts_with("0x") { &s[2..] } else { &s[..] };
+        if hex.len() != 40 {
+            return Err(de::Error::custom(format!("{} is not an address", s)));
+        }
+        Address::from_str(hex).map(Some).map_err(|_| de::Error::custom(format!("{} is not an address", s)))
+    }
+}
+
+fn parse_flag(s: &str) -> Option<bool> {
+    match s {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+fn to_dec(mut val: U256) -> String {
+    if val.is_zero() {
+        return "0".to_string();
+    }
+    let ten = U256::from(10);
+    let mut digits = Vec::new();
+    while !val.is_zero() {
+        digits.push(b'0' + (val % ten).low_u32() as u8);
+        val = val / ten;
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("digits are ASCII; qed")
+}
+
+fn parse_dec(s: &str) -> Option<U256> {
+    if s.is_empty() {
+        return None;
+    }
+    s.bytes().fold(Some(U256::zero()), |acc, b| {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        acc?.checked_mul(U256::from(10))?.checked_add(U256::from(b - b'0'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_decimal_u256() {
+        assert_eq!(parse_dec("0"), Some(U256::zero()));
+        assert_eq!(parse_dec("1000000000000000000"), Some(U256::from(1_000_000_000_000_000_000u64)));
+        assert_eq!(parse_dec("0x10"), None);
+        assert_eq!(parse_dec(""), None);
+        assert_eq!(parse_dec(&"9".repeat(80)), None);
+    }
+
+    #[test]
+    fn it_should_deserialize_token_transfers() {
+        let json = r#"{"blockNumber":"5000000","timeStamp":"1517799093","hash":"0x6f9e6ba0e3e8b1d3c7bfbd8d6b0b0d2a43cf5a0aa5ff4fb4a1aa5e2e80f5a3a1",
+            "nonce":"4","blockHash":"0x4b8d2a3b1ac0a8e3cd1b2db34e47a6ecb5ac4a9dc4ef8b31d0f1de3a1d42f4c0",
+            "from":"0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359","contractAddress":"0x86fa049857e0209aa7d9e616f7eb3b3b78ecfdb0",
+            "to":"0x0000000000000000000000000000000000000001","value":"250000000000000000000","tokenName":"EOS",
+            "tokenSymbol":"EOS","tokenDecimal":"18","transactionIndex":"12","gas":"60000","gasPrice":"1000000000",
+            "gasUsed":"37000","cumulativeGasUsed":"500000","input":"deprecated","confirmations":"1000"}"#;
+        let tx: EtherScanTokenTx = serde_json::from_str(json).unwrap();
+        assert_eq!(tx.block_number, 5_000_000);
+        assert_eq!(tx.transaction_index, 12);
+        assert_eq!(tx.value, U256::from(250u64) * U256::from(1_000_000_000_000_000_000u64));
+    }
+
+    #[test]
+    fn it_should_read_back_what_it_stores() {
+        let json = r#"{"blockNumber":"2000000","timeStamp":"1470173578","hash":"0x8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a2",
+            "from":"0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359","to":"","value":"1000000000000000000",
+            "contractAddress":"0x86fa049857e0209aa7d9e616f7eb3b3b78ecfdb0","input":"","type":"create",
+            "gas":"254995","gasUsed":"40000","traceId":"0_1","isError":"0","errCode":""}"#;
+        let tx: EtherScanInternalTx = serde_json::from_str(json).unwrap();
+        assert_eq!(tx.to, None);
+        assert_eq!(tx.contract_address, Some(Address::from("0x86fa049857e0209aa7d9e616f7eb3b3b78ecfdb0")));
+        assert_eq!(tx.value, U256::from(1_000_000_000_000_000_000u64));
+        assert!(!tx.is_error);
+        let record = EtherScanRecord::Internal(tx);
+        let stored = bincode::serialize(&record).unwrap();
+        assert_eq!(bincode::deserialize::<EtherScanRecord>(&stored).unwrap(), record);
+        assert_eq!(to_dec(U256::from(1_000_000_000_000_000_000u64)), "1000000000000000000");
+    }
+
+    #[test]
+    fn it_should_ignore_confirmations_when_comparing_listings() {
+        let json = |confirmations: u64| format!(r#"{{"blockNumber":"2000000","timeStamp":"1470173578",
+            "hash":"0x8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a2","nonce":"7",
+            "blockHash":"0x4b8d2a3b1ac0a8e3cd1b2db34e47a6ecb5ac4a9dc4ef8b31d0f1de3a1d42f4c0","transactionIndex":"3",
+            "from":"0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359","to":"0x0000000000000000000000000000000000000001",
+            "value":"1000000000000000000","gas":"21000","gasPrice":"20000000000","isError":"0","txreceipt_status":"",
+            "input":"0x","contractAddress":"","cumulativeGasUsed":"42000","gasUsed":"21000","confirmations":"{}"}}"#, confirmations);
+        let listed: EtherScanTx = serde_json::from_str(&json(1000)).unwrap();
+        let relisted: EtherScanTx = serde_json::from_str(&json(1500)).unwrap();
+        assert_ne!(listed.confirmations, relisted.confirmations);
+        assert_eq!(vec![EtherScanRecord::Normal(listed.clone())], vec![EtherScanRecord::Normal(relisted)]);
+        assert_ne!(listed, EtherScanTx { nonce: 8, ..listed.clone() });
+    }
 }
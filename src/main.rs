@@ -11,7 +11,9 @@ mod transaction_validator;
 mod etherscan;
 use failure::Error;
 
-use self::conf::{ChosenClient, Action};
+use self::conf::{ChosenClient, Action, Configuration, Source, Duplicates};
+use self::etherscan::EtherScan;
+use self::transaction_validator::{MergePolicy, source::{AppearanceSource, NodeTraces, NodeLogs, HashFile}};
 use web3::{
     types::{BlockNumber, H160},
     BatchTransport
@@ -26,12 +28,13 @@ use std::path::PathBuf;
 fn main() -> Result<(), Error>{
     pretty_env_logger::init();
     let conf = conf::Configuration::new()?;
-    let client = conf.get_client()?;
-    match conf.action {
-        Action::Validate{file, to, address} => {
-            match client {
-                ChosenClient::Http(c) => do_validate(c, to, address, file)?,
-                ChosenClient::Ipc(c) => do_validate(c, to, address, file)?,
+    match conf.action.clone() {
+        Action::Cache(cmd) => self::transaction_validator::admin::run(cmd)?,
+        Action::Validate{file, from, to, address, token_transfers, sources, hash_file, duplicates} => {
+            let validate = Validate { from, to, address, file, token_transfers, sources, hash_file, duplicates };
+            match conf.get_client()? {
+                ChosenClient::Http(c) => do_validate(c, &conf, validate)?,
+                ChosenClient::Ipc(c) => do_validate(c, &conf, validate)?,
                 _ => unimplemented!()
             }
         }
@@ -39,13 +42,47 @@ fn main() -> Result<(), Error>{
     Ok(())
 }
 
-fn do_validate<T>(mut client: Client<T>, to: Option<u64>, address: H160, file: PathBuf) -> Result<(), Error>
+/// the arguments of `Action::Validate`
+struct Validate {
+    from: Option<u64>,
+    to: Option<u64>,
+    address: H160,
+    file: PathBuf,
+    token_transfers: bool,
+    sources: Vec<Source>,
+    hash_file: Option<PathBuf>,
+    duplicates: Duplicates,
+}
+
+fn do_validate<T>(mut client: Client<T>, conf: &Configuration, validate: Validate) -> Result<(), Error>
 where
     T: BatchTransport + Send + Sync + 'static,
     <T as web3::Transport>::Out: Send
 {
-    let to = to.map(|t| BlockNumber::Number(t));
-    let fut = self::transaction_validator::TransactionValidator::new(&mut client, file, to, address)?
+    let mut sources: Vec<Box<dyn AppearanceSource<T>>> = Vec::new();
+    for source in validate.sources.iter() {
+        match source {
+            Source::EtherScan => {
+                // the explorer depends on the chain of the node
+                let mut eth_scan = EtherScan::new(&conf.explorer(client.node().chain_id)?)?;
+                eth_scan.token_transfers(validate.token_transfers);
+                sources.push(Box::new(eth_scan));
+            },
+            Source::Traces => sources.push(Box::new(NodeTraces)),
+            Source::Logs => sources.push(Box::new(NodeLogs)),
+        }
+    }
+    if let Some(hash_file) = validate.hash_file {
+        sources.push(Box::new(HashFile::new(hash_file)));
+    }
+    let merge = match validate.duplicates {
+        Duplicates::Keep => MergePolicy::KeepExisting,
+        Duplicates::Overwrite => MergePolicy::Overwrite,
+        Duplicates::Verify => MergePolicy::VerifyEqual,
+    };
+    let from = validate.from.map(|f| BlockNumber::Number(f));
+    let to = validate.to.map(|t| BlockNumber::Number(t));
+    let fut = self::transaction_validator::TransactionValidator::new(&mut client, &sources, merge, validate.file, from, to, validate.address)?
         .scan(&client)?
         .for_each(|v| {
             println!("{}", v);
@@ -1,6 +1,12 @@
-//! Warning! Uses Etherscan
+//! Validates a QuickBlocks CSV of an address against a local cache of the transactions
+//! its `AppearanceSource`s list, fetched from the node
 mod cache;
 mod simpledb;
+mod store;
+mod location;
+mod consistency;
+pub mod source;
+pub mod admin;
 pub mod err;
 
 use log::*;
@@ -8,7 +14,7 @@ use serde_derive::Deserialize;
 use failure::{Error, ResultExt};
 use rayon::prelude::*;
 use futures::{
-    future::Future,
+    future::{self, Future},
     stream::Stream,
     Poll,
     sync::mpsc::{self, UnboundedSender, UnboundedReceiver},
@@ -17,15 +23,26 @@ use web3::{
     BatchTransport,
     types::{Address, Trace, Transaction, TransactionId, BlockNumber, TransactionReceipt, H160, BlockId, Block as Web3Block, H256, Index},
 };
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use super::{
     utils,
-    etherscan::{EtherScan, SortType},
     client::{Client},
-    err::{ErrorKind, ValidateMsg},
+    err::{ErrorKind, ValidateMsg, ConfMsg},
 };
 
-use self::cache::{TxType, Block, TransactionCache as Cache};
+use self::simpledb::Provenance;
+use self::cache::{TxType, Block, Checkpoint, Reward, RewardKind, TransactionCache as Cache, FLUSH_BATCH};
+use self::location::Location;
+use self::consistency::Disagreement;
+use self::source::AppearanceSource;
+pub use self::cache::MergePolicy;
+
+/// number of transactions fetched between checkpoints
+const FETCH_CHUNK: usize = 500;
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct TxEntry  {
@@ -33,7 +50,7 @@ pub struct TxEntry  {
     block_num: u64,
     #[serde(rename = "transactionIndex")]
     transaction_index: usize,
-    location: String,
+    location: Location,
 }
 
 impl std::fmt::Display for TxEntry {
@@ -45,7 +62,17 @@ impl std::fmt::Display for TxEntry {
 #[derive(Debug, Clone)]
 pub enum InvalidEntry {
     Missing(H256),
-    Incorrect(TxEntry, Option<H256>) // hash is None if transction does not exist on ethereum mainnet
+    Incorrect(TxEntry, Option<H256>), // hash is None if transction does not exist on ethereum mainnet
+    /// the transaction includes the address, but not at the location the CSV claims
+    WrongLocation { entry: TxEntry, hash: H256, claimed: Location, expected: Vec<Location> },
+    /// the entry is outside of the block range being validated, and was not checked
+    OutOfRange(TxEntry),
+    /// the CSV lists a block or uncle reward the address did not receive
+    IncorrectReward(TxEntry),
+    /// the address received a block or uncle reward the CSV does not list
+    MissingReward(Reward),
+    /// EtherScan and the node disagree about a transaction of the address
+    Inconsistent(Disagreement),
 }
 
 impl std::fmt::Display for InvalidEntry {
@@ -59,70 +86,246 @@ impl std::fmt::Display for InvalidEntry {
                 }
             }
             InvalidEntry::Missing(hash) => write!(f, "Missing Transaction {:x}", hash),
+            InvalidEntry::WrongLocation { entry, hash, claimed, expected } => {
+                let expected = expected.iter().map(|l| l.to_string()).collect::<Vec<String>>();
+                write!(f, "Wrong Location at {} with hash: {:x}, claimed: {}, expected one of: [{}]",
+                       entry, hash, claimed, expected.join(", "))
+            }
+            InvalidEntry::OutOfRange(entry) => write!(f, "Out of Range Entry at {}, not validated", entry),
+            InvalidEntry::IncorrectReward(entry) => write!(f, "Incorrect Reward at {}, address is not the author", entry),
+            InvalidEntry::MissingReward(reward) => write!(f, "Missing {} Reward in block {}", reward.kind, reward.block),
+            InvalidEntry::Inconsistent(disagreement) => write!(f, "Inconsistent {}", disagreement),
         }
     }
 }
 
 pub struct TransactionValidator {
     csv: Vec<TxEntry>,
+    /// CSV entries outside of the block window being validated
+    out_of_range: Vec<TxEntry>,
     cache: Cache,
     addr: Address,
+    from: u64,
+    to: u64,
 }
 
 //TODO: skip DOS transactions (blocks 2283440 -- 2718436 with > 250 traces) #p1
 //https://medium.com/@tjayrush/defeating-the-ethereum-ddos-attacks-d3d773a9a063
 //
 impl TransactionValidator  {
-    //creates a new validator from genesis to specified block
-    pub fn new<T>(client: &mut Client<T>, csv_file: PathBuf, to_block: Option<BlockNumber>, address: H160)
+    /// creates a new validator for the window `from_block..=to_block`, checking the transactions `sources` list
+    /// defaults to genesis and latest block if not specified. `merge` decides what happens to data fetched twice
+    pub fn new<T>(client: &mut Client<T>, sources: &[Box<dyn AppearanceSource<T>>], merge: MergePolicy, csv_file: PathBuf, from_block: Option<BlockNumber>, to_block: Option<BlockNumber>, address: H160)
                   -> Result<Self, Error>
     where
         T: BatchTransport + Send + Sync + 'static
     {
+        // pin `latest` once, so the cache and every source see the same range
+        let latest = utils::latest_block(client);
+        let (from, to) = Self::window(from_block, to_block, latest)?;
+
         let mut csv_vec = Vec::new();
+        let mut out_of_range = Vec::new();
         let mut rdr = csv::Reader::from_path(csv_file.as_path())
             .with_context(|e| format!("Could not get csv file {}", e))?;
         for result in rdr.deserialize() {
             let res: TxEntry = result.context(ErrorKind::Validate(ValidateMsg::InvalidCsv))?;
-            csv_vec.push(res);
+            if res.block_num < from || res.block_num > to {
+                out_of_range.push(res);
+            } else {
+                csv_vec.push(res);
+            }
+        }
+        if !out_of_range.is_empty() {
+            warn!("Ignoring {} CSV entries outside of blocks {}..{}", out_of_range.len(), from, to);
         }
 
         // discard write handle; we should never modify the original CSV
-        let to_block = to_block.unwrap_or(BlockNumber::Latest);
         Ok(TransactionValidator {
             csv: csv_vec,
-            cache: Self::build_local_cache(client, to_block, address)?,
+            cache: Self::build_local_cache(client, sources, merge, from, to, address)?,
             addr: address,
+            out_of_range, from, to,
         })
     }
 
-    fn build_local_cache<T>(client: &mut Client<T>, to_block: BlockNumber, addr: H160) -> Result<Cache, Error>
+    /// the blocks `from_block..=to_block` resolve to with the chain head at `latest`. The window may not be empty.
+    /// It stops at `latest`, so that blocks mined after this run are never cached as fetched
+    fn window(from_block: Option<BlockNumber>, to_block: Option<BlockNumber>, latest: u64) -> Result<(u64, u64), Error> {
+        let from = utils::pin_block(from_block.unwrap_or(BlockNumber::Earliest), latest);
+        let mut to = utils::pin_block(to_block.unwrap_or(BlockNumber::Latest), latest);
+        if to > latest {
+            warn!("Block {} is past the latest block {}; stopping at {}", to, latest, latest);
+            to = latest;
+        }
+        if from > to {
+            return Err(ErrorKind::InvalidConfiguration(ConfMsg::EmptyRange(from, to)).into());
+        }
+        Ok((from, to))
+    }
+
+    fn build_local_cache<T>(client: &mut Client<T>, sources: &[Box<dyn AppearanceSource<T>>], merge: MergePolicy, from_block: u64, to_block: u64, addr: H160)
+                            -> Result<Cache, Error>
     where
         T: BatchTransport + Send + Sync + 'static,
     {
-        let mut cache = Cache::new(addr, BlockNumber::Earliest, to_block)?;
-        if cache.is_populated() {
+        let node = client.node().clone();
+        let mut cache = Cache::with_sources(node.chain_id, addr, from_block, to_block, sources.iter().flat_map(|s| s.lists()))?;
+        cache.set_merge_policy(merge);
+        let gaps = cache.gaps();
+        // without a source of rewards, reward rows are only checked against the node
+        let lists_rewards = sources.iter().any(|s| s.lists_rewards());
+        if gaps.is_empty() && cache.is_complete() && (!lists_rewards || cache.reward_gaps().is_empty()) {
             return Ok(cache);
         }
+        let fetched_at = SystemTime::now().duration_since(UNIX_EPOCH).context(ErrorKind::Internal)?.as_secs();
+        cache.set_provenance(Provenance {
+            chain_id: node.chain_id,
+            node: Provenance::node(&node.url),
+            client_version: node.client_version,
+            fetched_at,
+            // recorded by `save`, as what lists every block of the shard
+            sources: Vec::new(),
+        });
 
-        info!("gathering transactions from EtherScan");
-        let eth_scan = EtherScan::new();
-        let to_block = utils::as_u64(client, to_block);
-        let hashes = eth_scan.get_tx_by_account(client.ev_loop(), addr, 0, to_block, SortType::Ascending)?;
+        // SIGINT exits straight away again once the cache is built
+        let _interrupt = utils::catch_interrupt()?;
+        for (from_block, to_block) in gaps {
+            Self::fetch_range(client, &mut cache, sources, addr, from_block, to_block)?;
+        }
+        // a block can only be requested once the position of its transaction is known,
+        // so a second pass picks up the blocks of transactions repaired by the first
+        for _ in 0..2 {
+            cache.refresh()?;
+            let incomplete = cache.incomplete();
+            if incomplete.is_empty() {
+                break;
+            }
+            info!("Repairing {} incomplete transactions", incomplete.len());
+            let hashes = incomplete.into_iter().map(|(hash, _, block)| (hash, block)).collect::<Vec<_>>();
+            Self::fetch(client, &mut cache, &hashes)?;
+        }
+        let reward_gaps = if lists_rewards { cache.reward_gaps() } else { Vec::new() };
+        for (from_block, to_block) in reward_gaps {
+            let mut rewards = Vec::new();
+            for source in sources.iter().filter(|s| s.lists_rewards()) {
+                info!("gathering blocks and uncles mined in blocks {}..{} from {}", from_block, to_block, source.name());
+                rewards.extend(source.rewards(client, addr, from_block, to_block)?);
+            }
+            cache.insert_rewards((from_block, to_block), rewards);
+        }
+        info!("Finished building local cache. Saving...");
+        cache.save()?;
+        Ok(cache)
+    }
 
+    /// fetch every transaction of `addr` in `from_block..=to_block` into the cache
+    /// transactions are fetched in chunks, and a checkpoint is saved after each one,
+    /// so an interrupted build resumes from the last finished chunk.
+    /// The checkpoint of an earlier run that stopped short of `to_block` is finished first, and the rest of the range listed after it
+    fn fetch_range<T>(client: &mut Client<T>, cache: &mut Cache, sources: &[Box<dyn AppearanceSource<T>>], addr: H160, from_block: u64, to_block: u64)
+                      -> Result<(), Error>
+    where
+        T: BatchTransport + Send + Sync + 'static,
+    {
+        let listed_by = sources.iter().flat_map(|s| s.lists()).collect::<BTreeSet<String>>().into_iter().collect::<Vec<String>>();
+        let mut checkpoint = match cache.checkpoint(from_block)? {
+            // a list of other sources would be recorded as listed by these
+            Some(ref checkpoint) if checkpoint.sources != listed_by => {
+                info!("Listing blocks {}..{} again; they were listed by {} before", from_block, to_block, checkpoint.sources.join(", "));
+                cache.remove_checkpoint(from_block)?;
+                Self::gather(client, cache, sources, addr, from_block, to_block, listed_by)?
+            },
+            Some(checkpoint) => {
+                info!("Resuming blocks {}..{} from chunk {} of {}", from_block, checkpoint.to, checkpoint.done, checkpoint.chunks());
+                // transactions of chunks finished by an earlier run only need to be listed
+                for n in 0..checkpoint.done {
+                    for (hash, _) in checkpoint.chunk(n) {
+                        cache.adopt(*hash)?;
+                    }
+                }
+                checkpoint
+            },
+            None => Self::gather(client, cache, sources, addr, from_block, to_block, listed_by)?,
+        };
+
+        while checkpoint.done < checkpoint.chunks() {
+            if utils::interrupted() {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let hashes = checkpoint.chunk(checkpoint.done)
+                .iter()
+                .map(|(hash, block)| (*hash, Some(*block)))
+                .collect::<Vec<_>>();
+            Self::fetch(client, cache, &hashes)?;
+            checkpoint.done += 1;
+            cache.save_checkpoint(from_block, &checkpoint)?;
+            debug!("Fetched chunk {} of {} of blocks {}..{}", checkpoint.done, checkpoint.chunks(), from_block, checkpoint.to);
+        }
+        cache.mark_fetched((from_block, std::cmp::min(checkpoint.to, to_block)));
+        cache.remove_checkpoint(from_block)?;
+        if checkpoint.to < to_block {
+            Self::fetch_range(client, cache, sources, addr, checkpoint.to + 1, to_block)?;
+        }
+        Ok(())
+    }
+
+    /// list the transactions of `addr` in `from_block..=to_block`, and checkpoint the list before anything is fetched
+    fn gather<T>(client: &mut Client<T>, cache: &mut Cache, sources: &[Box<dyn AppearanceSource<T>>], addr: H160, from_block: u64, to_block: u64,
+                 listed_by: Vec<String>) -> Result<Checkpoint, Error>
+    where
+        T: BatchTransport + Send + Sync + 'static,
+    {
+        let candidates = source::gather(client, sources, addr, from_block, to_block)?;
+        // explorer records are stored with the transactions they list, and checked against the node by `scan`
+        for (_, records) in candidates.records {
+            cache.insert(TxType::from(records))?;
+        }
+        cache.flush()?;
+        let checkpoint = Checkpoint { to: to_block, sources: listed_by, hashes: candidates.hashes, chunk: FETCH_CHUNK, done: 0 };
+        cache.save_checkpoint(from_block, &checkpoint)?;
+        Ok(checkpoint)
+    }
+
+    /// fetch whatever is missing of the transactions `hashes` into the cache.
+    /// The block of a transaction is only fetched if its block number is known
+    fn fetch<T>(client: &mut Client<T>, cache: &mut Cache, hashes: &[(H256, Option<u64>)]) -> Result<(), Error>
+    where
+        T: BatchTransport + Send + Sync + 'static,
+    {
         // gather these in four asynchronous calls. This works best if the node being used
         // allows for 3+ threads for RPC calls
         let (txs, receipts, traces, blocks) = (client.batch(), client.batch(), client.batch(), client.batch());
 
         let mut block_numbers: Vec<(H256, u64)> = Vec::new();
+        let mut skipped = 0;
+        // (transactions, receipts, traces) requested. Empty batches are never submitted
+        let mut requested = (0, 0, 0);
         for (hash, block_num) in hashes.iter() {
-            txs.eth().transaction(TransactionId::Hash(*hash));
-            receipts.eth().transaction_receipt(*hash);
-            traces.trace().transaction(*hash);
+            // anything already in the shared store was fetched for another address
+            cache.adopt(*hash)?;
+            let (has_tx, has_receipt, has_traces) = cache.fetched(hash);
+            if has_tx && has_receipt && has_traces {
+                skipped += 1;
+            }
+            if !has_tx {
+                txs.eth().transaction(TransactionId::Hash(*hash));
+                requested.0 += 1;
+            }
+            if !has_receipt {
+                receipts.eth().transaction_receipt(*hash);
+                requested.1 += 1;
+            }
+            if !has_traces {
+                traces.trace().transaction(*hash);
+                requested.2 += 1;
+            }
             // only get block number if we haven't gotten it yet
             // associates a TXHash with a blocknumber. important later when we create Block{} struct
-            // make seperate storage for blocks
-            // deserialize into Bincode/Message pack and compress
+            let block_num = match block_num {
+                Some(n) if !cache.store().has_block(*n) => n,
+                _ => continue,
+            };
             match block_numbers.binary_search_by_key(block_num, |&(_, blk_num)| blk_num) {
                 Ok(_) => {}, //ele already exists
                 Err(pos) => {
@@ -131,12 +334,14 @@ impl TransactionValidator  {
                 }
             }
         }
+        info!("{} of {} transactions were already in the shared store", skipped, hashes.len());
 
         let (sender, receiver) = mpsc::unbounded();
         let txs = cache_get_task::<Transaction, _, _>(txs.transport(), sender.clone(), |val| {TxType::from(val)}); // .then here
         let receipts = cache_get_task::<TransactionReceipt, _, _>(receipts.transport(), sender.clone(), |val|{TxType::from(val)});
         let traces = cache_get_task::<Vec<Trace>, _, _>(traces.transport(), sender.clone(), |val| {TxType::from(val)});
 
+        let blocks_requested = block_numbers.len();
         let blocks = cache_get_task::<Web3Block<H256>, _, _>(blocks.transport(), sender, move |val| {
             let pos = &block_numbers
                 .binary_search_by_key(&val.number.expect(&verb_msg!("block num should not be pending")).as_u64(), |(_, blk_num)| *blk_num).unwrap();
@@ -144,21 +349,32 @@ impl TransactionValidator  {
             TxType::from(Block { tx_hash: tx, block: val })
         });
 
-        client.handle().spawn(txs);
-        client.handle().spawn(receipts);
-        client.handle().spawn(traces);
-        client.handle().spawn(blocks);
-
-        let fut = receiver.for_each(|tx_type| {
-            cache.insert(tx_type);
+        // tasks that are not spawned drop their sender, so the receiver still finishes
+        if requested.0 > 0 { client.handle().spawn(txs) }
+        if requested.1 > 0 { client.handle().spawn(receipts) }
+        if requested.2 > 0 { client.handle().spawn(traces) }
+        if blocks_requested > 0 { client.handle().spawn(blocks) }
+
+        // results are flushed to disk in batches as they arrive, so a range never has to fit in memory
+        let fut = receiver.map_err(|_| Error::from(ErrorKind::Async)).for_each(|tx_type| {
+            cache.insert(tx_type?)?;
+            if cache.pending() >= FLUSH_BATCH {
+                cache.flush()?;
+            }
+            if utils::interrupted() {
+                return Err(ErrorKind::Interrupted.into());
+            }
             Ok(())
         });
 
         info!("Submitting batch requests of Transactions, Receipts, and Traces");
-        client.run(fut).unwrap();
-        info!("Finished building local cache. Saving...");
-        cache.save()?;
-        Ok(cache)
+        let res = client.run(fut);
+        // whatever arrived is kept, even if the fetch was interrupted
+        cache.flush()?;
+        if utils::interrupted() {
+            return Err(ErrorKind::Interrupted.into());
+        }
+        res
     }
 
     // attempts to validate csv list of transactions, returning any incorrectly included
@@ -171,7 +387,14 @@ impl TransactionValidator  {
         <T as web3::Transport>::Out: Send
     {
         let (tx, rx): (UnboundedSender<InvalidEntry>, UnboundedReceiver<InvalidEntry>) = mpsc::unbounded();
+        for entry in self.out_of_range.iter() {
+            tx.unbounded_send(InvalidEntry::OutOfRange(entry.clone())).context(ErrorKind::Async)?;
+        }
         self.find_misplaced(client, tx.clone())?;
+        self.find_unrewarded(client, tx.clone())?;
+        self.find_mislocated(tx.clone())?;
+        self.find_inconsistent(tx.clone())?;
+        self.find_missing(tx)?;
         Ok(Scan { inner: rx })
     }
 
@@ -181,7 +404,8 @@ impl TransactionValidator  {
         T: BatchTransport + Send + Sync + 'static,
         <T as web3::Transport>::Out: Send,
     {
-        let mut csv = self.csv.clone();
+        // rewards are not transactions; handled by `find_unrewarded`
+        let mut csv = self.csv.iter().filter(|e| e.location.reward().is_none()).cloned().collect::<Vec<_>>();
         let mut new_csv = Vec::new();
         let mut added:  Vec<(u64, usize)> = Vec::new();
         for entry in csv {
@@ -201,10 +425,10 @@ impl TransactionValidator  {
         csv.par_iter().for_each(|entry| {
             let entry = entry.clone();
             // check if the transaction is included in our cache
-            if let None = self.cache.tx_by_blocknum_index(entry.block_num, entry.transaction_index) {
+            if let None = self.cache.txhash_by_blocknum_index(entry.block_num, entry.transaction_index) {
                 let sender_async = sender.clone();
                 // our database did not find the transaction -- but that does not yet mean the transaction is incorrectly included in quickblocks.
-                // etherscan does not report transactions with the address included as 'data'.
+                // sources may not list transactions with the address included as 'data'.
                 // query our node for the transaction to check if it is in the input
                 let fut = eth
                     .transaction(TransactionId::Block(BlockId::Number(BlockNumber::Number(entry.block_num as u64)), Index::from(entry.transaction_index)))
@@ -223,6 +447,127 @@ impl TransactionValidator  {
         });
         Ok(())
     }
+
+    /// find rewards listed in the CSV that the address did not receive.
+    /// sources may not list every reward, so one missing from the cache is checked against the node
+    fn find_unrewarded<T>(&self, client: &Client<T>, sender: UnboundedSender<InvalidEntry>) -> Result<(), Error>
+    where
+        T: BatchTransport + Send + Sync + 'static,
+        <T as web3::Transport>::Out: Send,
+    {
+        let remote = client.remote();
+        let eth = client.web3.eth();
+        for entry in self.csv.iter() {
+            let kind = match entry.location.reward() {
+                Some(kind) => kind,
+                None => continue,
+            };
+            if self.cache.has_reward(&Reward { block: entry.block_num, kind }) {
+                continue;
+            }
+            let block = BlockId::Number(BlockNumber::Number(entry.block_num));
+            let (entry, sender_async, addr, eth_uncles) = (entry.clone(), sender.clone(), self.addr, eth.clone());
+            let fut = eth
+                .block(block.clone())
+                .and_then(move |b| -> Box<dyn Future<Item = Vec<Address>, Error = web3::Error> + Send> {
+                    // authors of the block, or of its uncles
+                    match (kind, b) {
+                        (_, None) => Box::new(future::ok(Vec::new())),
+                        (RewardKind::Miner, Some(b)) => Box::new(future::ok(vec![b.author])),
+                        (RewardKind::Uncle, Some(b)) => {
+                            let uncles = (0..b.uncles.len()).map(|i| eth_uncles.uncle(block.clone(), Index::from(i)));
+                            Box::new(future::join_all(uncles).map(|uncles| {
+                                uncles.into_iter().filter_map(|u| u.map(|u| u.author)).collect()
+                            }))
+                        },
+                    }
+                })
+                .then(move |authors| {
+                    let authors = try_web3!(authors);
+                    if !authors.contains(&addr) {
+                        sender_async.unbounded_send(InvalidEntry::IncorrectReward(entry)).unwrap();
+                    }
+                    Ok(())
+                });
+            remote.spawn(|_| {fut});
+        }
+        Ok(())
+    }
+
+    /// find CSV entries whose location does not match where the address appears in the transaction
+    /// entries are checked in CSV order, so transactions are paged in from the store in the order they were fetched
+    fn find_mislocated(&self, sender: UnboundedSender<InvalidEntry>) -> Result<(), Error> {
+        self.csv.iter().try_for_each(|entry| {
+            if entry.location.reward().is_some() {
+                return Ok(()); // handled by `find_unrewarded`
+            }
+            let hash = match self.cache.txhash_by_blocknum_index(entry.block_num, entry.transaction_index) {
+                Some(h) => h,
+                None => return Ok(()), // not cached; handled by `find_misplaced`
+            };
+            let tx = self.cache.get(&hash)?.expect("hash was found in cache; qed");
+            if !entry.location.verifiable(&tx) {
+                debug!("Cannot verify location {} of {:x}; data not cached", entry.location, hash);
+                return Ok(());
+            }
+            let expected = Location::appearances(&self.addr, &tx);
+            if !expected.iter().any(|location| location.matches(&entry.location)) {
+                let invalid = InvalidEntry::WrongLocation {
+                    claimed: entry.location.clone(),
+                    entry: entry.clone(),
+                    hash, expected,
+                };
+                sender.unbounded_send(invalid).context(ErrorKind::Async)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// find cached transactions EtherScan and the node disagree about
+    fn find_inconsistent(&self, sender: UnboundedSender<InvalidEntry>) -> Result<(), Error> {
+        self.cache.txs_in_blocks(self.from, self.to).try_for_each(|(_, hash)| {
+            let tx = self.cache.get(&hash)?.expect("hash was found in cache; qed");
+            for disagreement in consistency::check(hash, &tx) {
+                sender.unbounded_send(InvalidEntry::Inconsistent(disagreement)).context(ErrorKind::Async)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// find transactions and rewards that exist in our cache but were not included in the CSV.
+    /// Sources may list transactions the address does not appear in, so only a transaction the address
+    /// appears in on the node, or one an explorer listed for it, is missing
+    fn find_missing(&self, sender: UnboundedSender<InvalidEntry>) -> Result<(), Error> {
+        let mut listed: Vec<(u64, usize)> = self.csv
+            .iter()
+            .map(|entry| (entry.block_num, entry.transaction_index))
+            .collect();
+        listed.sort();
+        listed.dedup();
+        let mut rewards: Vec<Reward> = self.csv
+            .iter()
+            .filter_map(|entry| entry.location.reward().map(|kind| Reward { block: entry.block_num, kind }))
+            .collect();
+        rewards.sort();
+        rewards.dedup();
+
+        self.cache.txs_in_blocks(self.from, self.to).try_for_each(|(pos, hash)| -> Result<(), Error> {
+            if listed.binary_search(&pos).is_ok() {
+                return Ok(());
+            }
+            let tx = self.cache.get(&hash)?.expect("hash was found in cache; qed");
+            if tx.explorer.is_some() || !Location::appearances(&self.addr, &tx).is_empty() {
+                sender.unbounded_send(InvalidEntry::Missing(hash)).context(ErrorKind::Async)?;
+            }
+            Ok(())
+        })?;
+        self.cache.rewards_in_blocks(self.from, self.to).try_for_each(|reward| {
+            if rewards.binary_search(reward).is_err() {
+                sender.unbounded_send(InvalidEntry::MissingReward(*reward)).context(ErrorKind::Async)?;
+            }
+            Ok(())
+        })
+    }
 }
 
 fn scan_bytes(addr: H160, bytes: &Vec<u8>) -> bool {
@@ -230,36 +575,48 @@ fn scan_bytes(addr: H160, bytes: &Vec<u8>) -> bool {
 }
 
 
-/// asynchronously send a batch request
+/// asynchronously send a batch request.
+/// A batch the node could not answer at all is sent on as an error, which ends the fetch
 // decides on conversion through the predicate F
-fn cache_get_task<A, T, F>(batch: &web3::transports::Batch<T>, sender: UnboundedSender<TxType>, fun: F) -> impl Future<Item=(), Error=()>
+fn cache_get_task<A, T, F>(batch: &web3::transports::Batch<T>, sender: UnboundedSender<Result<TxType, Error>>, fun: F) -> impl Future<Item=(), Error=()>
 where
     A: serde::de::DeserializeOwned,
     T: BatchTransport,
     F: Fn(A) -> TxType,
 {
     batch.submit_batch()
-        .map_err(|e| ErrorKind::Network(format!("{}", e)).into())
-        .and_then(|vals| {
+        .then(move |vals| {
+            let vals = match vals {
+                Ok(vals) => vals,
+                Err(e) => {
+                    let err = ErrorKind::Network(format!("{}", e)).into();
+                    if let Err(e) = sender.unbounded_send(Err(err)) {
+                        error!("{}", verb_msg!("{}", e));
+                    }
+                    return Ok(());
+                }
+            };
+            // a value the node could not provide is left out. Its entry is recorded as incomplete, and repaired later
             let res = vals.into_iter()
-                .map(|val| {
-                    serde_json::from_value(try_web3!(val)).map_err(|e| e.into())
+                .filter_map(|val| {
+                    let parsed = match val {
+                        Ok(v) => serde_json::from_value(v).map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    match parsed {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            warn!("Skipping value the node could not provide: {}", e);
+                            None
+                        }
+                    }
                 })
-                .collect::<Result<Vec<A>, Error>>();
-            futures::future::result(res)
-        }).and_then(move |vals| {
-            match vals
-                .into_iter()
-                .try_for_each(|val| sender.unbounded_send(fun(val))) {
-                    Err(e) => error!("{}", verb_msg!("{}", e)),
-                    Ok(_) => (),
-                };
+                .collect::<Vec<A>>();
+            if let Err(e) = res.into_iter().try_for_each(|val| sender.unbounded_send(Ok(fun(val)))) {
+                error!("{}", verb_msg!("{}", e));
+            }
             drop(sender);
-            Ok(())
-        }).map_err(|e| {
-            error!("{}", verb_msg!("{}", e));
-            error!("{:?}", e);
-            panic!("Shutting down");
+            Ok::<(), ()>(())
         })
 }
 
@@ -282,11 +639,17 @@ mod tests {
     use super::*;
     use web3::types::Address;
     use crate::conf::Configuration;
+    use crate::etherscan::EtherScan;
     use test::Bencher;
 
-    fn tx_validator(client: &mut Client<web3::transports::http::Http>) -> TransactionValidator {
+    fn tx_validator(client: &mut Client<web3::transports::http::Http>, conf: &Configuration) -> TransactionValidator {
+        let eth_scan = EtherScan::new(&conf.explorer(client.node().chain_id).expect("Could not configure explorer")).expect("Could not set up explorer client");
+        let sources: Vec<Box<dyn AppearanceSource<web3::transports::http::Http>>> = vec![Box::new(eth_scan)];
         match TransactionValidator::new(client,
+                                       &sources,
+                                       MergePolicy::default(),
                                        PathBuf::from("/home/insi/Projects/absentis/tx_list.csv"),
+                                       None,
                                        Some(BlockNumber::Number(1_000_000)),
                                        Address::from("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"))
         {
@@ -304,12 +667,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_should_reject_empty_windows() {
+        assert_eq!(TransactionValidator::window(None, None, 100).unwrap(), (0, 100));
+        assert_eq!(TransactionValidator::window(Some(BlockNumber::Number(50)), None, 100).unwrap(), (50, 100));
+        assert!(TransactionValidator::window(Some(BlockNumber::Number(101)), None, 100).is_err());
+        assert!(TransactionValidator::window(Some(BlockNumber::Number(20)), Some(BlockNumber::Number(10)), 100).is_err());
+        assert_eq!(TransactionValidator::window(Some(BlockNumber::Number(50)), Some(BlockNumber::Number(150)), 100).unwrap(), (50, 100));
+        assert!(TransactionValidator::window(Some(BlockNumber::Number(120)), Some(BlockNumber::Number(150)), 100).is_err());
+    }
+
+    fn transaction(hash: u64, index: u64, from: &str, to: &str) -> Transaction {
+        let json = format!(r#"{{"hash":"0x{:064x}","nonce":"0x0",
+            "blockHash":"0x0000000000000000000000000000000000000000000000000000000000000001","blockNumber":"0x1e8480",
+            "transactionIndex":"0x{:x}","from":"{}","to":"{}","value":"0x0","gasPrice":"0x0","gas":"0x0","input":"0x"}}"#,
+            hash, index, from, to);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn it_should_not_report_unrelated_candidates_as_missing() {
+        const CHAIN: u64 = 9_000_025;
+        let addr = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+        let other = "0x0000000000000000000000000000000000000001";
+        let mut cache = Cache::scratch(CHAIN, Address::from(addr));
+        // a hash file listed a transaction the address does not appear in, along with one it sent
+        cache.insert(TxType::from(transaction(1, 0, other, other))).unwrap();
+        cache.insert(TxType::from(transaction(2, 1, addr, other))).unwrap();
+        let validator = TransactionValidator {
+            csv: Vec::new(), out_of_range: Vec::new(), cache,
+            addr: Address::from(addr),
+            from: 0, to: 2_000_000,
+        };
+        let (tx, rx) = mpsc::unbounded();
+        validator.find_missing(tx).unwrap();
+        let missing = rx.collect().wait().unwrap();
+        Cache::remove_scratch(CHAIN);
+        assert_eq!(missing.len(), 1);
+        match &missing[0] {
+            InvalidEntry::Missing(hash) => assert_eq!(*hash, H256::from(2)),
+            other => panic!("expected a missing transaction, got {}", other),
+        }
+    }
+
     #[test]
     fn it_should_create_new_validator() {
         pretty_env_logger::try_init();
         let conf = Configuration::new().expect("Could not create configuration");
         let mut client = Client::<web3::transports::http::Http>::new_http(&conf).expect("Could not build client");
-        let _validator = tx_validator(&mut client);
+        let _validator = tx_validator(&mut client, &conf);
     }
 
     #[test]
@@ -317,7 +723,7 @@ mod tests {
         pretty_env_logger::try_init();
         let conf = Configuration::new().expect("Could not create configuration");
         let mut client = Client::<web3::transports::http::Http>::new_http(&conf).expect("Could not build client");
-        let validator = tx_validator(&mut client);
+        let validator = tx_validator(&mut client, &conf);
         let fut = validator.scan(&client).unwrap().for_each(|inv| {
             info!("Invalid Transaction: {}", inv);
             Ok(())
@@ -330,7 +736,7 @@ mod tests {
         b.iter(|| {
             let conf = Configuration::new().expect("Could not create configuration");
             let mut client = Client::<web3::transports::http::Http>::new_http(&conf).expect("Could not build client");
-            let validator = tx_validator(&mut client);
+            let validator = tx_validator(&mut client, &conf);
             let fut = validator.scan(&client).unwrap().for_each(|inv| {
                 Ok(())
             });
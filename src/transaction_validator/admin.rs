@@ -0,0 +1,249 @@
+//! `absentis cache` commands, for looking into and cleaning up the local cache without a node.
+//! `list`, `inspect`, `verify` and `export` only read; `purge` and `compact` write
+use log::*;
+use failure::{Error, ResultExt};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, Write, BufWriter},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use web3::types::H160;
+use crate::{
+    err::ErrorKind,
+    conf::{CacheCommand, TxRef, ExportFormat},
+};
+
+use super::{
+    cache::{TransactionCache as Cache, Reward},
+    location::Location,
+    simpledb::Provenance,
+};
+
+const DAY: u64 = 60 * 60 * 24;
+/// chain of commands that read a single cache, if no chain is given
+const MAINNET: u64 = 1;
+
+pub fn run(cmd: CacheCommand) -> Result<(), Error> {
+    match cmd {
+        CacheCommand::List{chain} => list(chain),
+        CacheCommand::Inspect{chain, address, transaction} => inspect(chain.unwrap_or(MAINNET), address, transaction),
+        CacheCommand::Verify{chain, address} => verify(chain, address),
+        CacheCommand::Purge{chain, address, older_than} => purge(chain, address, older_than),
+        CacheCommand::Compact{chain, address} => compact(chain, address),
+        CacheCommand::Export{chain, address, format, output} => export(chain.unwrap_or(MAINNET), address, format, output),
+    }
+}
+
+/// every cache shard of `chain`, or of every chain, and the size of the shared store of each chain
+fn list(chain: Option<u64>) -> Result<(), Error> {
+    let shards = Cache::shards()?.into_iter().filter(|s| on(chain, s.chain)).collect::<Vec<_>>();
+    if shards.is_empty() {
+        println!("Cache is empty");
+    }
+    for shard in shards.iter() {
+        let format = match shard.header.as_ref() {
+            Some(h) => format!("v{} {}", h.version, h.codec),
+            None => "empty".to_string(),
+        };
+        let incomplete = match shard.incomplete {
+            Some(n) => format!("{} incomplete", n),
+            None => "completeness unknown".to_string(),
+        };
+        println!("chain {}  0x{:x}  blocks {}..{}  {} entries ({})  {}  {}",
+                 shard.chain, shard.addr, shard.range.0, shard.range.1, shard.entries, incomplete, human_size(shard.size), format);
+        if let Some(p) = shard.header.as_ref().and_then(|h| h.provenance.as_ref()) {
+            println!("    fetched from {} ({}) at {} (unix time)", Provenance::node(&p.node), p.client_version, p.fetched_at);
+            if !p.sources.is_empty() {
+                println!("    listed by {}", p.sources.join(", "));
+            }
+        }
+    }
+    for chain in Cache::chains()?.into_iter().filter(|c| on(chain, *c)) {
+        let store = Cache::store_path(chain)?;
+        if store.is_dir() {
+            let mut size = 0;
+            for entry in std::fs::read_dir(store.as_path()).context(ErrorKind::Cache)? {
+                size += entry.context(ErrorKind::Cache)?.metadata().context(ErrorKind::Cache)?.len();
+            }
+            println!("Shared store of chain {}: {} in {:?}", chain, human_size(size), store);
+        }
+    }
+    Ok(())
+}
+
+/// print a cached transaction
+fn inspect(chain: u64, addr: H160, transaction: TxRef) -> Result<(), Error> {
+    let cache = open(chain, addr)?;
+    let tx = match transaction {
+        TxRef::Hash(hash) => cache.get(&hash)?,
+        TxRef::Position(block, index) => cache.tx_by_blocknum_index(block, index)?,
+    };
+    match tx {
+        Some(tx) => {
+            println!("{}", serde_json::to_string_pretty(&tx).context(ErrorKind::Cache)?);
+            Ok(())
+        },
+        None => {
+            error!("Transaction {:?} is not in the cache of 0x{:x}", transaction, addr);
+            Err(ErrorKind::Cache.into())
+        }
+    }
+}
+
+/// check that every cached transaction is complete
+fn verify(chain: Option<u64>, addr: Option<H160>) -> Result<(), Error> {
+    let caches = Cache::shards()?.into_iter()
+        .filter(|s| on(chain, s.chain) && addr.map(|a| a == s.addr).unwrap_or(true))
+        .map(|s| (s.chain, s.addr))
+        .collect::<BTreeSet<(u64, H160)>>();
+    let mut incomplete = 0;
+    for (chain, addr) in caches {
+        let cache = open(chain, addr)?;
+        let hashes = cache.hashes();
+        let mut complete = 0;
+        for hash in hashes.iter() {
+            let tx = cache.get(hash)?.expect("hash is in the cache; qed");
+            let missing = tx.completeness().missing();
+            if missing.is_empty() {
+                complete += 1;
+            } else {
+                println!("0x{:x}: {:x} is missing {}", addr, hash, missing.join(", "));
+            }
+        }
+        println!("0x{:x}: {} of {} transactions complete", addr, complete, hashes.len());
+        incomplete += hashes.len() - complete;
+    }
+    if incomplete > 0 {
+        error!("{} cached transactions are incomplete", incomplete);
+        return Err(ErrorKind::Cache.into());
+    }
+    Ok(())
+}
+
+/// remove the shards of `addr`, or shards last written more than `older_than` days ago, or both
+fn purge(chain: Option<u64>, addr: Option<H160>, older_than: Option<u64>) -> Result<(), Error> {
+    // so that a cache in the old layout can be purged too
+    Cache::migrate_layout()?;
+    let now = SystemTime::now();
+    for shard in Cache::shards()? {
+        if !on(chain, shard.chain) || addr.map(|a| a != shard.addr).unwrap_or(false) {
+            continue;
+        }
+        if let Some(days) = older_than {
+            let age = now.duration_since(shard.modified).unwrap_or(Duration::from_secs(0));
+            if age < Duration::from_secs(days * DAY) {
+                continue;
+            }
+        }
+        std::fs::remove_file(shard.path.as_path()).context(ErrorKind::Cache)?;
+        println!("Removed chain {} 0x{:x} blocks {}..{}", shard.chain, shard.addr, shard.range.0, shard.range.1);
+    }
+    Ok(())
+}
+
+/// merge consecutive or overlapping shards of `addr`, or of every address, into one shard each
+fn compact(chain: Option<u64>, addr: Option<H160>) -> Result<(), Error> {
+    Cache::migrate_layout()?;
+    let caches = Cache::shards()?.into_iter()
+        .filter(|s| on(chain, s.chain) && addr.map(|a| a == s.addr).unwrap_or(true))
+        .map(|s| (s.chain, s.addr))
+        .collect::<BTreeSet<(u64, H160)>>();
+    for (chain, addr) in caches {
+        for (from, to) in Cache::compact(chain, addr)? {
+            println!("Compacted chain {} 0x{:x} into blocks {}..{}", chain, addr, from, to);
+        }
+    }
+    Ok(())
+}
+
+/// write every cached transaction of `addr`, ordered by position
+fn export(chain: u64, addr: H160, format: ExportFormat, output: Option<PathBuf>) -> Result<(), Error> {
+    let cache = open(chain, addr)?;
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).context(ErrorKind::Cache)?)),
+        None => Box::new(io::stdout()),
+    };
+    match format {
+        ExportFormat::Json => export_json(&cache, out),
+        ExportFormat::Csv => export_csv(&cache, addr, out),
+    }
+}
+
+/// a JSON array of every `Tx`, written one transaction at a time
+fn export_json(cache: &Cache, mut out: Box<dyn Write>) -> Result<(), Error> {
+    out.write_all(b"[").context(ErrorKind::Cache)?;
+    for (i, hash) in cache.hashes().iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",").context(ErrorKind::Cache)?;
+        }
+        out.write_all(b"\n").context(ErrorKind::Cache)?;
+        let tx = cache.get(hash)?.expect("hash is in the cache; qed");
+        serde_json::to_writer(&mut out, &tx).context(ErrorKind::Cache)?;
+    }
+    out.write_all(b"\n]\n").context(ErrorKind::Cache)?;
+    out.flush().context(ErrorKind::Cache)?;
+    Ok(())
+}
+
+/// a row for every location `addr` appears at, with the columns of a QuickBlocks CSV and the transaction hash.
+/// Rewards get a row after the transactions of their block, without a hash
+fn export_csv(cache: &Cache, addr: H160, out: Box<dyn Write>) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(&["blockNum", "transactionIndex", "location", "hash"]).context(ErrorKind::Cache)?;
+    let reward_row = |reward: &Reward| vec![reward.block.to_string(), reward.kind.index().to_string(), reward.kind.to_string(), String::new()];
+    let mut rewards = cache.rewards_in_blocks(0, u64::max_value()).peekable();
+    for hash in cache.hashes() {
+        let tx = cache.get(&hash)?.expect("hash is in the cache; qed");
+        let (block, index) = match tx.position() {
+            Some(pos) => pos,
+            None => {
+                warn!("Skipping {:x}; position unknown", hash);
+                continue;
+            }
+        };
+        while let Some(reward) = rewards.peek().filter(|r| r.block < block).cloned() {
+            wtr.write_record(&reward_row(reward)).context(ErrorKind::Cache)?;
+            rewards.next();
+        }
+        let row = |location: String| vec![block.to_string(), index.to_string(), location, format!("0x{:x}", hash)];
+        let locations = Location::appearances(&addr, &tx);
+        if locations.is_empty() {
+            wtr.write_record(&row(String::new())).context(ErrorKind::Cache)?;
+        }
+        for location in locations {
+            wtr.write_record(&row(location.to_string())).context(ErrorKind::Cache)?;
+        }
+    }
+    for reward in rewards {
+        wtr.write_record(&reward_row(reward)).context(ErrorKind::Cache)?;
+    }
+    wtr.flush().context(ErrorKind::Cache)?;
+    Ok(())
+}
+
+/// every cached block of `addr` on `chain`, opened without writing to disk
+fn open(chain: u64, addr: H160) -> Result<Cache, Error> {
+    Cache::open_read_only(chain, addr)
+}
+
+/// whether `chain` is selected by the `--chain` filter
+fn on(filter: Option<u64>, chain: u64) -> bool {
+    filter.map(|c| c == chain).unwrap_or(true)
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -1,39 +1,295 @@
 //! A transaction cache for transaction_validator
 use log::*;
 use serde_derive::*;
-use failure::{Error, ResultExt};
+use serde::{Serialize, de::DeserializeOwned};
+use failure::{Error, Fail, ResultExt};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, BTreeMap, BTreeSet},
     path::PathBuf,
+    str::FromStr,
+    time::SystemTime,
+};
+use web3::types::{Transaction, TransactionReceipt, Trace, Log, H160, H256, Block as Web3Block};
+use crate::{
+    err::ErrorKind,
+    etherscan::EtherScanRecord,
 };
-use rayon::prelude::*;
-use web3::types::{Transaction, TransactionReceipt, Trace, Log, H160, H256, U256, BlockNumber, Block as Web3Block, U128};
-use crate::err::ErrorKind;
 
 use super::{
     // intermediary_types::{self as db_types,TxInt, LogInt},
-    simpledb::SimpleDB,
+    simpledb::{SimpleDB, Header, Provenance},
+    store::Store,
+    err::CacheError,
 };
 
+/// chain of caches written before caches were namespaced by chain. Absentis only used mainnet EtherScan then
+crate const LEGACY_CHAIN: u64 = 1;
+/// what listed the transactions of a shard written before sources were recorded
+const LEGACY_SOURCES: [&str; 1] = ["EtherScan"];
+
 /// a simple cache for storing transactions
+/// A cache is made up of shards on disk, each covering a range of blocks for one address.
+/// Every chain has its own cache directory, so data from one chain is never read as data from another.
+/// Transaction data lives in the shared `Store` and is read from disk when it is looked up;
+/// only hashes, positions, and entries fetched since the last `flush()` are held in memory
 #[derive(Debug)]
 pub struct TransactionCache {
-    /// the cache
-    cache: HashMap<H256, Tx>,
-    /// name of an object in a database in OS temporary directory.
-    name: String, // -- name convention = ADDRESS_FROMBLOCK_TOBLOCK
-    db: SimpleDB<HashMap<H256, Tx>>,
-    populated: bool,
+    /// entries fetched since the last flush
+    pending: HashMap<H256, Tx>,
+    /// every transaction of the address in this cache
+    entries: HashMap<H256, Entry>,
+    /// (block number, transaction index) -> transaction hash, for every entry with a known position
+    index: BTreeMap<(u64, usize), H256>,
+    addr: H160,
+    /// id of the chain the cached transactions are from
+    chain: u64,
+    /// where the cached transactions were fetched from. Recorded in the header of saved shards
+    provenance: Option<Provenance>,
+    /// the range of blocks that was requested for this cache
+    range: (u64, u64),
+    /// what lists the transactions of fetched blocks. Shards not listed by every one of these are fetched again
+    sources: BTreeSet<String>,
+    /// shards this cache was loaded from
+    shards: Vec<Shard>,
+    /// ranges of blocks fetched since the cache was loaded
+    fetched: Vec<(u64, u64)>,
+    /// blocks and uncles mined by the address
+    rewards: BTreeSet<Reward>,
+    /// ranges of blocks the rewards were fetched for since the cache was loaded
+    reward_ranges: Vec<(u64, u64)>,
+    /// chain data shared with the caches of other addresses
+    store: Store,
+    /// what to do with items inserted more than once
+    merge: MergePolicy,
+}
+
+/// what `TransactionCache::insert` does with an item that is already in the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// keep the cached item, and drop the new one
+    KeepExisting,
+    /// replace the cached item with the new one
+    Overwrite,
+    /// keep the cached item if it is equal to the new one, and error if it is not
+    VerifyEqual,
+}
+
+impl Default for MergePolicy {
+    fn default() -> MergePolicy {
+        MergePolicy::VerifyEqual
+    }
+}
+
+/// number of pending entries that are flushed to the store at once
+crate const FLUSH_BATCH: usize = 1024;
+
+/// progress of fetching a range of blocks, so that an interrupted build resumes where it stopped.
+/// A checkpoint is kept by the block its range starts at, since the end of a range moves with the chain head
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+crate struct Checkpoint {
+    /// last block of the range the transactions were listed for
+    crate to: u64,
+    /// what listed the transactions, as `AppearanceSource::lists` names it. Sorted
+    crate sources: Vec<String>,
+    /// every transaction the appearance sources listed for the range, with its block number
+    crate hashes: Vec<(H256, u64)>,
+    /// number of transactions fetched at a time
+    crate chunk: usize,
+    /// number of chunks fetched and flushed to the store
+    crate done: usize,
+}
+
+impl Checkpoint {
+    crate fn chunks(&self) -> usize {
+        (self.hashes.len() + self.chunk - 1) / self.chunk
+    }
+
+    /// the transactions of chunk `n`
+    crate fn chunk(&self, n: usize) -> &[(H256, u64)] {
+        let start = std::cmp::min(n * self.chunk, self.hashes.len());
+        let end = std::cmp::min(start + self.chunk, self.hashes.len());
+        &self.hashes[start..end]
+    }
+}
+
+/// a file in the OS cache directory listing the transactions of an address for a range of blocks
+#[derive(Debug)]
+struct Shard {
+    range: (u64, u64),
+    db: SimpleDB<ShardData>, // -- name convention = ADDRESS_FROMBLOCK_TOBLOCK
+    /// whether the shard lists the rewards of its range
+    rewards: bool,
+    /// what listed the transactions of the shard
+    sources: BTreeSet<String>,
+}
+
+/// a block-level appearance of the address: a reward for mining a block or an uncle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Reward {
+    /// the mined block, or the block that included the mined uncle, as QuickBlocks lists it
+    pub block: u64,
+    pub kind: RewardKind,
 }
 
-/// A transaction and all associated information (Transaction, Receipt, Traces, Extra Logs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum RewardKind {
+    /// the address is the author of the block
+    Miner,
+    /// the address is the author of an uncle of the block
+    Uncle,
+}
+
+impl std::fmt::Display for RewardKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RewardKind::Miner => write!(f, "miner"),
+            RewardKind::Uncle => write!(f, "uncle"),
+        }
+    }
+}
+
+/// what a shard records about each transaction of its address
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+struct Entry {
+    /// (block number, transaction index), once the transaction or receipt has been fetched
+    position: Option<(u64, usize)>,
+    complete: Completeness,
+}
+
+/// which parts of a transaction were successfully fetched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Completeness {
+    pub transaction: bool,
+    pub receipt: bool,
+    pub traces: bool,
+    pub block: bool,
+}
+
+impl Completeness {
+    crate fn is_complete(&self) -> bool {
+        self.transaction && self.receipt && self.traces && self.block
+    }
+
+    /// names of the parts that are missing
+    crate fn missing(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if !self.transaction { missing.push("transaction") }
+        if !self.receipt { missing.push("receipt") }
+        if !self.traces { missing.push("traces") }
+        if !self.block { missing.push("block") }
+        missing
+    }
+
+    /// what the shared store holds for the transaction `hash` included in block `position.0`
+    fn stored(store: &Store, hash: &H256, position: Option<(u64, usize)>) -> Completeness {
+        Completeness {
+            transaction: store.has_transaction(hash),
+            receipt: store.has_receipt(hash),
+            traces: store.has_traces(hash),
+            block: position.map(|(block, _)| store.has_block(block)).unwrap_or(false),
+        }
+    }
+}
+
+/// contents of a shard on disk
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+enum ShardData {
+    /// the transactions the address appears in and what has been fetched of them, with the rewards of the address.
+    /// The data itself lives in the shared `Store`
+    Rewards { entries: Vec<(H256, Entry)>, rewards: Vec<Reward> },
+    /// shards written before rewards were recorded, or whose rewards could not be fetched
+    Entries(Vec<(H256, Entry)>),
+    /// shards written before completeness was recorded
+    Positions(Vec<(H256, Option<(u64, usize)>)>),
+    /// shards written before positions were recorded
+    Appearances(Vec<H256>),
+    /// shards written before the shared store existed hold every transaction in full
+    Full(HashMap<H256, Tx>),
+}
+
+impl ShardData {
+    /// number of transactions listed
+    fn len(&self) -> usize {
+        match self {
+            ShardData::Rewards { entries, .. } => entries.len(),
+            ShardData::Entries(e) => e.len(),
+            ShardData::Positions(p) => p.len(),
+            ShardData::Appearances(a) => a.len(),
+            ShardData::Full(txs) => txs.len(),
+        }
+    }
+
+    /// number of transactions recorded as incomplete. `None` if the shard predates completeness records
+    fn incomplete(&self) -> Option<usize> {
+        match self {
+            ShardData::Rewards { entries: e, .. } | ShardData::Entries(e) => {
+                Some(e.iter().filter(|(_, entry)| !entry.complete.is_complete()).count())
+            },
+            _ => None,
+        }
+    }
+}
+
+/// a shard on disk, as described by `absentis cache list`
+#[derive(Debug, Clone)]
+crate struct ShardInfo {
+    crate chain: u64,
+    crate addr: H160,
+    crate range: (u64, u64),
+    crate path: PathBuf,
+    /// number of transactions listed in the shard
+    crate entries: usize,
+    /// number of those that were not completely fetched when the shard was saved
+    crate incomplete: Option<usize>,
+    /// bytes on disk
+    crate size: u64,
+    crate modified: SystemTime,
+    /// `None` if the shard was never saved
+    crate header: Option<Header>,
+}
+
+impl Default for ShardData {
+    fn default() -> ShardData {
+        ShardData::Entries(Vec::new())
+    }
+}
+
+/// A transaction and all associated information (Transaction, Receipt, Traces, Logs)
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Tx {
     pub transaction: Option<Transaction>,
     pub receipt: Option<TransactionReceipt>,
     pub traces: Option<Vec<Trace>>,
-    pub logs: Option<Log>,
-    pub block: Option<Block>
+    /// every log of the receipt
+    pub logs: Option<Vec<Log>>,
+    pub block: Option<Block>,
+    /// what EtherScan listed for the transaction
+    #[serde(default)]
+    pub explorer: Option<Vec<EtherScanRecord>>,
+}
+
+impl Tx {
+    /// the (block number, transaction index) this transaction was included at.
+    /// Falls back to the receipt if the transaction itself was not fetched
+    crate fn position(&self) -> Option<(u64, usize)> {
+        if let Some(tx) = self.transaction.as_ref() {
+            Some((tx.block_number?.as_u64(), tx.transaction_index?.as_u64() as usize))
+        } else {
+            let receipt = self.receipt.as_ref()?;
+            Some((receipt.block_number?.as_u64(), receipt.transaction_index.as_u64() as usize))
+        }
+    }
+
+    /// which parts of this transaction are present
+    crate fn completeness(&self) -> Completeness {
+        Completeness {
+            transaction: self.transaction.is_some(),
+            receipt: self.receipt.is_some(),
+            traces: self.traces.is_some(),
+            block: self.block.is_some(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -41,8 +297,9 @@ pub enum TxType {
     Transaction(Transaction),
     Receipt(TransactionReceipt),
     Traces(Vec<Trace>),
-    Logs(Log),
-    Block(Block)
+    Logs(Vec<Log>),
+    Block(Block),
+    Explorer(Vec<EtherScanRecord>),
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -59,125 +316,732 @@ impl std::fmt::Display for TxType {
             TxType::Traces(_) => write!(f, "Traces"),
             TxType::Logs(_) => write!(f, "Logs"),
             TxType::Block(_) => write!(f, "Block"),
+            TxType::Explorer(_) => write!(f, "Explorer Records"),
         }
     }
 }
 
-trait Displayable {
-    fn display(&self) -> String;
-}
-impl Displayable for BlockNumber {
-    fn display(&self) -> String {
-        match self {
-            BlockNumber::Earliest => "earliest".to_string(),
-            BlockNumber::Latest => "latest".to_string(),
-            BlockNumber::Number(num) => num.to_string(),
-            BlockNumber::Pending => "pending".to_string(),
-        }
+impl TransactionCache {
+    /// create a new Cache of `addr` on chain `chain`
+    /// blocks must be concrete numbers, so that a cache is never keyed on a moving chain head
+    /// every shard of `addr` that overlaps `from_block..=to_block` is loaded;
+    /// blocks not covered by any shard are reported by `gaps()`.
+    /// A shard recorded as fetched from a different chain is refused
+    pub fn new(chain: u64, addr: H160, from_block: u64, to_block: u64) -> Result<Self, Error> {
+        Self::migrate_layout()?;
+        let store = Store::open(Self::store_path(chain)?)?;
+        Self::load(chain, addr, from_block, to_block, None, store, false)
     }
-}
 
-// TODO: Make this a generic cache for H256 hashes #p3
-// TODO: support drawing from multiple db files
-//     - ie if an address is included multiple times like:
-//     - {ADDR}_0_1000000_2000000 , {ADDR}_2000000_3000000
-//     - and the user asks for transactions from 0 - 3000000, use both files instead of creating a new one
-//     - or include a function to 'merge' consecutive files into {ADDR}_0_3000000
-// doesn't do miner
-impl TransactionCache {
-    /// create a new Cache
-    pub fn new(addr: H160, from_block: BlockNumber, to_block: BlockNumber) -> Result<Self, Error> {
-        let name = format!("0x{:x}_{}_{}.bin", addr, from_block.display(), to_block.display());
-        info!("FILE: {:?}", Self::try_local(&name)?);
-        let db = Self::try_local(&name)?;
-        let cache = db.get().context(ErrorKind::Cache)?;
-        info!("cache.len(): {}", cache.len());
+    /// create a new Cache like `new`, for blocks listed by `sources`, as `AppearanceSource::lists` names them.
+    /// The transactions of a shard that was not listed by every one of them are left out,
+    /// and `gaps()` reports its blocks to be listed again
+    crate fn with_sources(chain: u64, addr: H160, from_block: u64, to_block: u64, sources: impl IntoIterator<Item = String>)
+                          -> Result<Self, Error>
+    {
+        Self::migrate_layout()?;
+        let store = Store::open(Self::store_path(chain)?)?;
+        Self::load(chain, addr, from_block, to_block, Some(sources.into_iter().collect()), store, false)
+    }
+
+    /// an empty cache of `addr` on `chain`, for tests. Whatever was cached on `chain` before is removed
+    #[cfg(test)]
+    crate fn scratch(chain: u64, addr: H160) -> Self {
+        let _ = std::fs::remove_dir_all(Self::dir_path(chain).unwrap());
+        let store = Store::open(Self::store_path(chain).unwrap()).unwrap();
+        Self::load(chain, addr, 0, u64::max_value(), None, store, false).unwrap()
+    }
+
+    /// remove the cache of `chain` made by `scratch`
+    #[cfg(test)]
+    crate fn remove_scratch(chain: u64) {
+        std::fs::remove_dir_all(Self::dir_path(chain).unwrap()).unwrap();
+    }
+
+    /// every cached block of `addr` on `chain`, for looking into the cache.
+    /// Nothing on disk is created, moved or rewritten: shards in an older format are read as they are,
+    /// and a cache in the layout before chain namespacing is warned about and left where it is
+    crate fn open_read_only(chain: u64, addr: H160) -> Result<Self, Error> {
+        let store = Store::open_read_only(Self::store_path(chain)?)?;
+        Self::load(chain, addr, 0, u64::max_value(), None, store, true)
+    }
+
+    /// without `sources`, blocks are fetched with what listed every loaded shard
+    fn load(chain: u64, addr: H160, from_block: u64, to_block: u64, sources: Option<BTreeSet<String>>, mut store: Store, read_only: bool)
+            -> Result<Self, Error>
+    {
+        let mut pending = HashMap::new();
+        let mut entries = HashMap::new();
+        let mut index = BTreeMap::new();
+        let mut shards = Vec::new();
+        let mut rewards = BTreeSet::new();
+        let mut provenance: Option<Provenance> = None;
+        for range in Self::discover(chain, &addr)? {
+            if range.0 > to_block || range.1 < from_block {
+                continue;
+            }
+            let db = SimpleDB::<ShardData>::new(Self::db_path(chain, &Self::name(&addr, range.0, range.1))?)?;
+            info!("Loading cache shard for blocks {}..{}", range.0, range.1);
+            if !read_only {
+                db.migrate().context(ErrorKind::Cache)?;
+            }
+            let shard_provenance = db.header().context(ErrorKind::Cache)?.and_then(|h| h.provenance);
+            let listed_by: BTreeSet<String> = match shard_provenance.as_ref().map(|p| &p.sources) {
+                Some(sources) if !sources.is_empty() => sources.iter().cloned().collect(),
+                _ => LEGACY_SOURCES.iter().map(|s| s.to_string()).collect(),
+            };
+            if let Some(p) = shard_provenance {
+                if p.chain_id != chain {
+                    error!("Cache shard {:?} was fetched from {} on chain {}", db.path(), Provenance::node(&p.node), p.chain_id);
+                    return Err(ErrorKind::WrongChain(p.chain_id, chain).into());
+                }
+                if provenance.as_ref().map(|latest| latest.fetched_at < p.fetched_at).unwrap_or(true) {
+                    provenance = Some(p);
+                }
+            }
+            let mut has_rewards = false;
+            let shard_entries = match db.get().context(ErrorKind::Cache)? {
+                ShardData::Rewards { entries, rewards: shard_rewards } => {
+                    rewards.extend(shard_rewards);
+                    has_rewards = true;
+                    entries
+                },
+                ShardData::Entries(entries) => entries,
+                ShardData::Positions(positions) => positions.into_iter()
+                    .map(|(h, position)| (h, Entry { position, complete: Completeness::default() }))
+                    .collect(),
+                ShardData::Appearances(list) => {
+                    info!("Recording positions of shard for blocks {}..{}", range.0, range.1);
+                    list.into_iter()
+                        .map(|h| Ok((h, Entry { position: store.position(&h)?, complete: Completeness::default() })))
+                        .collect::<Result<Vec<_>, Error>>()?
+                },
+                ShardData::Full(txs) => {
+                    let shard_entries = txs.iter()
+                        .map(|(h, tx)| (*h, Entry { position: tx.position(), complete: Completeness::default() }))
+                        .collect::<Vec<_>>();
+                    if read_only {
+                        // held like fetched entries, which are never flushed by a read-only cache
+                        pending.extend(txs);
+                    } else {
+                        info!("Moving shard for blocks {}..{} into the shared store", range.0, range.1);
+                        store.absorb(txs)?;
+                    }
+                    shard_entries
+                }
+            };
+            // rewards do not depend on what lists transactions, and the transactions of a Full shard are kept in the store
+            if !sources.as_ref().map(|s| listed_by.is_superset(s)).unwrap_or(true) {
+                info!("Shard for blocks {}..{} was listed by {}; listing it again", range.0, range.1,
+                      listed_by.iter().cloned().collect::<Vec<String>>().join(", "));
+                shards.push(Shard { range, db, rewards: has_rewards, sources: listed_by });
+                continue;
+            }
+            for (hash, mut entry) in shard_entries {
+                // the store is the source of truth; a record can be stale if the store was removed,
+                // or if another address has since fetched what was missing
+                entry.complete = Completeness::stored(&store, &hash, entry.position);
+                if let Some(pos) = entry.position {
+                    index.insert(pos, hash);
+                }
+                entries.insert(hash, entry);
+            }
+            shards.push(Shard { range, db, rewards: has_rewards, sources: listed_by });
+        }
+        let sources = sources.or_else(|| {
+            shards.iter().map(|s| s.sources.clone()).fold(None, |every: Option<BTreeSet<String>>, listed| Some(match every {
+                Some(every) => every.intersection(&listed).cloned().collect(),
+                None => listed,
+            }))
+        });
+        let incomplete = entries.values().filter(|e| !e.complete.is_complete()).count();
+        info!("cache.len(): {}, {} incomplete", entries.len(), incomplete);
         Ok(TransactionCache {
-            populated: cache.len() > 0,
-            cache, name, db,
+            range: (from_block, to_block),
+            merge: MergePolicy::default(),
+            fetched: Vec::new(),
+            reward_ranges: Vec::new(),
+            sources: sources.unwrap_or_default(),
+            entries, index, addr, chain, provenance, shards, store, rewards, pending,
         })
     }
 
+    /// record that data inserted into this cache is fetched from the node described by `provenance`
+    crate fn set_provenance(&mut self, provenance: Provenance) {
+        self.provenance = Some(provenance);
+    }
+
+
+    /// the chain data shared with other addresses
+    crate fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// add the transaction `hash` to this cache, with whatever the shared store already holds for it
+    crate fn adopt(&mut self, hash: H256) -> Result<(), Error> {
+        if !self.entries.contains_key(&hash) {
+            let position = self.store.position(&hash)?;
+            if let Some(pos) = position {
+                self.index.insert(pos, hash);
+            }
+            let complete = Completeness::stored(&self.store, &hash, position);
+            self.entries.insert(hash, Entry { position, complete });
+        }
+        Ok(())
+    }
+
+    /// every transaction that is missing a part, with what was fetched of it and the block it is in, if known
+    crate fn incomplete(&self) -> Vec<(H256, Completeness, Option<u64>)> {
+        self.entries.iter()
+            .filter(|(_, e)| !e.complete.is_complete())
+            .map(|(h, e)| (*h, e.complete, e.position.map(|p| p.0)))
+            .collect()
+    }
+
+    /// flush pending entries, and record what the store now holds for every entry
+    crate fn refresh(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        let store = &self.store;
+        for (hash, entry) in self.entries.iter_mut() {
+            entry.complete = Completeness::stored(store, hash, entry.position);
+        }
+        Ok(())
+    }
+
+    /// whether the (transaction, receipt, traces) of `hash` have been fetched
+    crate fn fetched(&self, hash: &H256) -> (bool, bool, bool) {
+        let pending = self.pending.get(hash);
+        (
+            self.store.has_transaction(hash) || pending.map(|tx| tx.transaction.is_some()).unwrap_or(false),
+            self.store.has_receipt(hash) || pending.map(|tx| tx.receipt.is_some()).unwrap_or(false),
+            self.store.has_traces(hash) || pending.map(|tx| tx.traces.is_some()).unwrap_or(false),
+        )
+    }
+
+    /// ranges of blocks that no fetched range, or loaded shard listed by the sources of this cache, covers.
+    /// A shard listed by other sources is a gap as a whole, so that a saved shard is listed by the same sources throughout
+    crate fn gaps(&self) -> Vec<(u64, u64)> {
+        let covered = self.shards.iter()
+            .filter(|s| s.sources.is_superset(&self.sources))
+            .map(|s| s.range)
+            .chain(self.fetched.iter().cloned())
+            .collect::<Vec<(u64, u64)>>();
+        gaps(self.span(), &covered)
+    }
+
+    /// record that every transaction of `range` has been listed in the cache
+    crate fn mark_fetched(&mut self, range: (u64, u64)) {
+        self.fetched.push(range);
+    }
+
+    /// ranges of blocks in the span of this cache that the rewards of the address have not been fetched for
+    crate fn reward_gaps(&self) -> Vec<(u64, u64)> {
+        let covered = self.shards.iter()
+            .filter(|s| s.rewards)
+            .map(|s| s.range)
+            .chain(self.reward_ranges.iter().cloned())
+            .collect::<Vec<(u64, u64)>>();
+        gaps(self.span(), &covered)
+    }
+
+    /// record `rewards` as every reward of the address in `range`
+    crate fn insert_rewards(&mut self, range: (u64, u64), rewards: impl IntoIterator<Item = Reward>) {
+        self.rewards.extend(rewards);
+        self.reward_ranges.push(range);
+    }
+
+    /// whether `reward` is a cached reward of the address
+    crate fn has_reward(&self, reward: &Reward) -> bool {
+        self.rewards.contains(reward)
+    }
+
+    /// every cached reward in blocks `from_block..=to_block`, ordered by block
+    crate fn rewards_in_blocks(&self, from_block: u64, to_block: u64) -> impl Iterator<Item = &Reward> + '_ {
+        let from = Reward { block: from_block, kind: RewardKind::Miner };
+        let to = Reward { block: to_block, kind: RewardKind::Uncle };
+        self.rewards.range(from..=to)
+    }
+
+    /// the blocks a saved shard of this cache covers; every shard it was loaded from and the requested range
+    fn span(&self) -> (u64, u64) {
+        let start = self.shards.iter().map(|s| s.range.0).chain(Some(self.range.0)).min().expect("chain is never empty; qed");
+        let end = self.shards.iter().map(|s| s.range.1).chain(Some(self.range.1)).max().expect("chain is never empty; qed");
+        (start, end)
+    }
+
+    /// merge every run of consecutive or overlapping shards of `addr` on `chain` into a single shard.
+    /// Returns the ranges of the merged shards
+    crate fn compact(chain: u64, addr: H160) -> Result<Vec<(u64, u64)>, Error> {
+        let runs = runs(Self::discover(chain, &addr)?);
+        for (from, to) in runs.iter() {
+            info!("Compacting shards of 0x{:x} into blocks {}..{}", addr, from, to);
+            Self::new(chain, addr, *from, *to)?.save()?;
+        }
+        Ok(runs)
+    }
+
+    /// what `insert` does with items that are already in the cache
+    crate fn set_merge_policy(&mut self, policy: MergePolicy) {
+        self.merge = policy;
+    }
+
     /// Insert a TxType into Cache
-    /// handle errors with .exists() to make sure we're not overwriting anything
-    /// it is the job of the caller of insert() to make sure that no objects passed to insert have already been inserted for a tx hash
-    /// Normally, this means that no TXhashes should be duplicates when getting information from the node
-    pub fn insert(&mut self, tx: impl CacheAction) {
-        if tx.exists(&self.cache) {
-            error!("Transaction already exists in cache. Aborting...");
-            std::process::exit(1);
-        } else {
-            tx.insert(&mut self.cache);
+    /// an item that was already inserted or stored for its transaction hash is merged according to the cache's `MergePolicy`.
+    /// This happens when a source lists a transaction more than once, or a resumed build receives data again.
+    /// Data already flushed to the shared store is replaced in the store
+    crate fn insert(&mut self, tx: impl CacheAction) -> Result<(), Error> {
+        let hash = *tx.hash();
+        if tx.exists(&self.pending) {
+            if !self.replaces(&tx, &self.pending)? {
+                return Ok(());
+            }
+        } else if self.entries.contains_key(&hash) {
+            let stored = std::iter::once((hash, self.store.tx(&hash)?)).collect::<HashMap<H256, Tx>>();
+            if tx.exists(&stored) {
+                if self.replaces(&tx, &stored)? {
+                    self.store.overwrite(std::iter::once((hash, tx.empty())))?;
+                    self.reposition(hash)?;
+                }
+                return Ok(());
+            }
+        }
+        tx.insert(&mut self.pending);
+        self.reindex(hash);
+        Ok(())
+    }
+
+    /// whether `tx`, which is already in `cached`, replaces the cached item. Errors if the policy is to verify, and they differ
+    fn replaces(&self, tx: &impl CacheAction, cached: &HashMap<H256, Tx>) -> Result<bool, Error> {
+        match self.merge {
+            MergePolicy::KeepExisting => {
+                debug!("Keeping cached {} of {:x}", tx.name(), tx.hash());
+                Ok(false)
+            },
+            MergePolicy::Overwrite => {
+                debug!("Overwriting cached {} of {:x}", tx.name(), tx.hash());
+                Ok(true)
+            },
+            MergePolicy::VerifyEqual if tx.matches(cached) => Ok(false),
+            MergePolicy::VerifyEqual => {
+                Err(CacheError::Conflict(tx.name(), *tx.hash()).context(ErrorKind::Cache).into())
+            }
+        }
+    }
+
+    /// hashes of every transaction in the cache, ordered by position. Transactions without a known position come last
+    crate fn hashes(&self) -> Vec<H256> {
+        let mut hashes: Vec<H256> = self.index.values().cloned().collect();
+        hashes.extend(self.entries.iter().filter(|(_, e)| e.position.is_none()).map(|(h, _)| *h));
+        hashes
+    }
+
+    /// number of entries waiting to be flushed to the store
+    crate fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// append every pending entry to the shared store, and drop it from memory
+    crate fn flush(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
         }
+        debug!("Flushing {} entries to the shared store", self.pending.len());
+        let pending = std::mem::replace(&mut self.pending, HashMap::new());
+        self.store.absorb(pending)
     }
 
-    /// extend cache with a vector of CacheAction Types
-    crate fn extend(&mut self, val: Vec<impl CacheAction>) {
-        self.cache.extend(val.into_iter().map(|x| (x.hash().clone(), x.empty())))
+    /// read the position of `hash` again, once its records in the store were replaced
+    fn reposition(&mut self, hash: H256) -> Result<(), Error> {
+        let position = self.store.position(&hash)?;
+        let entry = self.entries.get_mut(&hash).expect("only called for cached entries; qed");
+        if let Some(old) = entry.position.take() {
+            self.index.remove(&old);
+        }
+        entry.position = position;
+        if let Some(pos) = position {
+            self.index.insert(pos, hash);
+        }
+        Ok(())
+    }
+
+    /// update the entry and position index of `hash` with what has been fetched for it
+    fn reindex(&mut self, hash: H256) {
+        let tx = self.pending.get(&hash).expect("only called after inserting; qed");
+        let fetched = tx.completeness();
+        let entry = self.entries.entry(hash).or_insert(Entry { position: None, complete: Completeness::default() });
+        if let Some(pos) = tx.position() {
+            // an overwritten transaction may have moved
+            if let Some(old) = entry.position.replace(pos) {
+                self.index.remove(&old);
+            }
+            self.index.insert(pos, hash);
+        }
+        entry.complete.transaction |= fetched.transaction;
+        entry.complete.receipt |= fetched.receipt;
+        entry.complete.traces |= fetched.traces;
+        entry.complete.block |= fetched.block;
     }
 
     /// get a transaction hash from cache by block number
     crate fn txhash_by_blocknum_index(&self, block_num: u64, index: usize) -> Option<H256> {
-        let block_num = U256::from(block_num);
-        let index = U128::from(index);
-        self.cache.par_iter()
-            .find_any(|(_, v)| v.transaction.as_ref().unwrap().block_number.expect("Block number will never be pending; qed") == block_num && v.transaction.as_ref().unwrap().transaction_index.unwrap() == index)
-            .map(|(k, _)| k.clone())
+        self.index.get(&(block_num, index)).cloned()
     }
 
-    crate fn get(&self, tx_hash: &H256) -> Option<&Tx> {
-        self.cache.get(tx_hash)
+    /// positions and hashes of every cached transaction in blocks `from_block..=to_block`,
+    /// ordered by block number and transaction index
+    crate fn txs_in_blocks(&self, from_block: u64, to_block: u64) -> impl Iterator<Item = ((u64, usize), H256)> + '_ {
+        self.index
+            .range((from_block, 0)..=(to_block, usize::max_value()))
+            .map(|(pos, hash)| (*pos, *hash))
     }
 
-    crate fn tx_by_blocknum_index(&self, block_num: u64, index: usize) -> Option<&Tx> {
-        let tx_hash = self.txhash_by_blocknum_index(block_num, index)?;
-        self.get(&tx_hash)
+    /// read the transaction `tx_hash` from the store, along with anything fetched for it that is not flushed yet
+    crate fn get(&self, tx_hash: &H256) -> Result<Option<Tx>, Error> {
+        if !self.entries.contains_key(tx_hash) {
+            return Ok(None);
+        }
+        let mut tx = self.store.tx(tx_hash)?;
+        if let Some(pending) = self.pending.get(tx_hash) {
+            tx.transaction = tx.transaction.or_else(|| pending.transaction.clone());
+            tx.receipt = tx.receipt.or_else(|| pending.receipt.clone());
+            tx.traces = tx.traces.or_else(|| pending.traces.clone());
+            tx.logs = tx.logs.or_else(|| pending.logs.clone());
+            tx.block = tx.block.or_else(|| pending.block.clone());
+            tx.explorer = tx.explorer.or_else(|| pending.explorer.clone());
+        }
+        Ok(Some(tx))
     }
 
-    // clones cache
-    /// Save all transactions to a temporary database that lives in system cache directory by cloning
+    crate fn tx_by_blocknum_index(&self, block_num: u64, index: usize) -> Result<Option<Tx>, Error> {
+        match self.txhash_by_blocknum_index(block_num, index) {
+            Some(tx_hash) => self.get(&tx_hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Flush pending transactions to the shared store, and write the list of transactions and what was
+    /// fetched of them as one shard spanning every shard it was loaded from. The shards it supersedes are removed.
+    /// Rewards are only written if they are known for the whole span, so a shard never lists part of them
     crate fn save(&mut self) -> Result<(), Error> {
-        Ok(self.db.save(self.cache.clone()).context(ErrorKind::Cache)?)
+        let gaps = self.gaps();
+        if !gaps.is_empty() {
+            error!("Refusing to save cache with unfetched blocks: {:?}", gaps);
+            return Err(ErrorKind::Cache.into());
+        }
+        let (start, end) = self.span();
+        let has_rewards = self.reward_gaps().is_empty();
+        if !has_rewards {
+            warn!("Saving cache without rewards; they will be fetched on the next run");
+        }
+        self.refresh()?;
+        let incomplete = self.entries.values().filter(|e| !e.complete.is_complete()).count();
+        if incomplete > 0 {
+            warn!("Saving cache with {} incomplete transactions; they will be repaired on the next run", incomplete);
+        }
+        let entries = self.entries.iter().map(|(h, e)| (*h, *e)).collect();
+        let data = if has_rewards {
+            ShardData::Rewards { entries, rewards: self.rewards.iter().cloned().collect() }
+        } else {
+            ShardData::Entries(entries)
+        };
+        let db = Self::try_local::<ShardData>(self.chain, &Self::name(&self.addr, start, end))?;
+        // every block of the shard is listed by at least the sources of this cache
+        let provenance = self.provenance.clone().map(|p| Provenance { sources: self.sources.iter().cloned().collect(), ..p });
+        db.save_with(data, provenance).context(ErrorKind::Cache)?;
+
+        for shard in self.shards.drain(..) {
+            if shard.range != (start, end) {
+                info!("Removing superseded cache shard {:?}", shard.db.path());
+                std::fs::remove_file(shard.db.path()).context(ErrorKind::Cache)?;
+            }
+        }
+        self.shards.push(Shard { range: (start, end), db, rewards: has_rewards, sources: self.sources.clone() });
+        Ok(())
+    }
+
+    /// whether every part of every transaction in the cache has been fetched
+    crate fn is_complete(&self) -> bool {
+        self.pending.is_empty() && self.entries.values().all(|e| e.complete.is_complete())
+    }
+
+    /// every shard in the cache directory, of every address on every chain.
+    /// Only reads; a cache in the layout before chain namespacing is warned about, and not listed
+    crate fn shards() -> Result<Vec<ShardInfo>, Error> {
+        if !Self::legacy_files()?.is_empty() {
+            warn!("{:?} holds a cache written before chain namespacing; \
+                   it is moved into the cache of chain {} by the next validation or `cache compact`",
+                  Self::root_path()?, LEGACY_CHAIN);
+        }
+        let mut shards = Vec::new();
+        for chain in Self::chains()? {
+            for entry in std::fs::read_dir(Self::dir_path(chain)?).context(ErrorKind::Cache)? {
+                let entry = entry.context(ErrorKind::Cache)?;
+                let (addr, from, to) = match entry.file_name().to_str().and_then(Self::parse_name) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let meta = entry.metadata().context(ErrorKind::Cache)?;
+                let db = SimpleDB::<ShardData>::new(entry.path())?;
+                let header = db.header().context(ErrorKind::Cache)?;
+                let data = if header.is_some() { db.get().context(ErrorKind::Cache)? } else { ShardData::default() };
+                shards.push(ShardInfo {
+                    range: (from, to),
+                    path: entry.path(),
+                    size: meta.len(),
+                    modified: meta.modified().context(ErrorKind::Cache)?,
+                    entries: data.len(),
+                    incomplete: data.incomplete(),
+                    chain, addr, header,
+                });
+            }
+        }
+        shards.sort_by_key(|s| (s.chain, s.addr, s.range));
+        Ok(shards)
+    }
+
+    /// ids of every chain with a cache directory
+    crate fn chains() -> Result<Vec<u64>, Error> {
+        let root = Self::root_path()?;
+        if !root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut chains = Vec::new();
+        for entry in std::fs::read_dir(root).context(ErrorKind::Cache)? {
+            let entry = entry.context(ErrorKind::Cache)?;
+            if let Some(chain) = entry.file_name().to_str().and_then(Self::parse_chain) {
+                if entry.path().is_dir() {
+                    chains.push(chain);
+                }
+            }
+        }
+        chains.sort();
+        Ok(chains)
+    }
+
+    /// move a cache written before caches were namespaced by chain into the directory of `LEGACY_CHAIN`
+    crate fn migrate_layout() -> Result<(), Error> {
+        let legacy = Self::legacy_files()?;
+        if legacy.is_empty() {
+            return Ok(());
+        }
+        let dir = Self::dir_path(LEGACY_CHAIN)?;
+        info!("Moving cache written before chain namespacing to {:?}", dir);
+        std::fs::create_dir_all(dir.as_path()).context(ErrorKind::Cache)?;
+        for path in legacy {
+            let target = dir.join(path.file_name().expect("read from a directory listing; qed"));
+            if target.exists() {
+                warn!("Not moving {:?}; {:?} already exists", path, target);
+                continue;
+            }
+            std::fs::rename(path.as_path(), target).context(ErrorKind::Cache)?;
+        }
+        Ok(())
     }
 
-    /// check if cache contains transactions
-    /// so far, this simply checks if the length of the underlying hashmap is > 0
-    crate fn is_populated(&self) -> bool {
-        self.populated
+    /// files of a cache written before caches were namespaced by chain
+    fn legacy_files() -> Result<Vec<PathBuf>, Error> {
+        let root = Self::root_path()?;
+        if !root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut legacy = Vec::new();
+        for entry in std::fs::read_dir(root.as_path()).context(ErrorKind::Cache)? {
+            let entry = entry.context(ErrorKind::Cache)?;
+            let name = entry.file_name();
+            let is_legacy = name.to_str().map(|n| {
+                n == "store" || Self::parse_name(n).is_some() || (n.starts_with("0x") && n.ends_with(".checkpoint"))
+            }).unwrap_or(false);
+            if is_legacy {
+                legacy.push(entry.path());
+            }
+        }
+        Ok(legacy)
     }
 
-    fn try_local(name: &str) -> Result<SimpleDB<HashMap<H256, Tx>>, Error> {
-        if Self::db_exists(name)? {
-            Ok(SimpleDB::<HashMap<H256, Tx>>::new(Self::db_path(name)?)?)
+    /// the checkpoint of an unfinished build of a range starting at block `from`, if there is one
+    crate fn checkpoint(&self, from: u64) -> Result<Option<Checkpoint>, Error> {
+        let path = Self::db_path(self.chain, &Self::checkpoint_name(&self.addr, from))?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        // a checkpoint written by another version is only progress, and can be thrown away
+        let checkpoint = match SimpleDB::<Checkpoint>::new(path)?.get() {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                warn!("Ignoring unreadable checkpoint of blocks from {}: {}", from, e);
+                return Ok(None);
+            }
+        };
+        if checkpoint.chunk == 0 {
+            return Ok(None);
+        }
+        Ok(Some(checkpoint))
+    }
+
+    crate fn save_checkpoint(&self, from: u64, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let db = Self::try_local::<Checkpoint>(self.chain, &Self::checkpoint_name(&self.addr, from))?;
+        db.save(checkpoint.clone()).context(ErrorKind::Cache)?;
+        Ok(())
+    }
+
+    /// remove the checkpoint of the range starting at block `from` once it is fully fetched
+    crate fn remove_checkpoint(&self, from: u64) -> Result<(), Error> {
+        let path = Self::db_path(self.chain, &Self::checkpoint_name(&self.addr, from))?;
+        if path.exists() {
+            std::fs::remove_file(path).context(ErrorKind::Cache)?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint_name(addr: &H160, from: u64) -> String {
+        format!("0x{:x}_{}.checkpoint", addr, from)
+    }
+
+    fn try_local<D>(chain: u64, name: &str) -> Result<SimpleDB<D>, Error>
+    where
+        D: DeserializeOwned + Serialize + Default
+    {
+        if Self::db_exists(chain, name)? {
+            Ok(SimpleDB::<D>::new(Self::db_path(chain, name)?)?)
         } else {
-            if !Self::dir_exists()? {
-                std::fs::create_dir(Self::dir_path()?)?;
+            if !Self::dir_exists(chain)? {
+                std::fs::create_dir_all(Self::dir_path(chain)?)?;
             }
-            Ok(SimpleDB::<HashMap<H256, Tx>>::new(Self::db_path(name)?)?)
+            Ok(SimpleDB::<D>::new(Self::db_path(chain, name)?)?)
         }
     }
 
-    fn db_path(name: &str) -> Result<PathBuf, Error> {
-        let mut dir = Self::dir_path().context(ErrorKind::Cache)?;
+    fn name(addr: &H160, from_block: u64, to_block: u64) -> String {
+        format!("0x{:x}_{}_{}.bin", addr, from_block, to_block)
+    }
+
+    /// parse the block range out of a cache file name belonging to `addr`
+    fn parse_range(addr: &H160, name: &str) -> Option<(u64, u64)> {
+        match Self::parse_name(name)? {
+            (a, from, to) if a == *addr => Some((from, to)),
+            _ => None,
+        }
+    }
+
+    /// parse the address and block range out of a cache file name
+    fn parse_name(name: &str) -> Option<(H160, u64, u64)> {
+        if !name.starts_with("0x") || !name.ends_with(".bin") {
+            return None;
+        }
+        let mut parts = name[2..name.len() - ".bin".len()].split('_');
+        let addr = parts.next()?;
+        if addr.len() != 40 {
+            return None;
+        }
+        let addr = H160::from_str(addr).ok()?;
+        let (from, to) = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((addr, from, to))
+    }
+
+    /// parse the chain id out of the name of a chain's cache directory
+    fn parse_chain(name: &str) -> Option<u64> {
+        if !name.starts_with("chain_") {
+            return None;
+        }
+        name["chain_".len()..].parse().ok()
+    }
+
+    /// ranges of every non-empty shard of `addr` in the cache directory of `chain`
+    fn discover(chain: u64, addr: &H160) -> Result<Vec<(u64, u64)>, Error> {
+        if !Self::dir_exists(chain)? {
+            return Ok(Vec::new());
+        }
+        let mut ranges = Vec::new();
+        for entry in std::fs::read_dir(Self::dir_path(chain)?).context(ErrorKind::Cache)? {
+            let entry = entry.context(ErrorKind::Cache)?;
+            let name = entry.file_name();
+            if let Some(range) = name.to_str().and_then(|n| Self::parse_range(addr, n)) {
+                // empty shards were created but never saved
+                if entry.metadata().context(ErrorKind::Cache)?.len() > 0 {
+                    ranges.push(range);
+                }
+            }
+        }
+        Ok(ranges)
+    }
+
+    fn db_path(chain: u64, name: &str) -> Result<PathBuf, Error> {
+        let mut dir = Self::dir_path(chain).context(ErrorKind::Cache)?;
         dir.push(name);
         Ok(dir)
     }
 
-    fn db_exists(name: &str) -> Result<bool, Error> {
-        let path = Self::db_path(name).context(ErrorKind::Cache)?;
+    fn db_exists(chain: u64, name: &str) -> Result<bool, Error> {
+        let path = Self::db_path(chain, name).context(ErrorKind::Cache)?;
         Ok(path.as_path().exists())
     }
 
-    fn dir_path() -> Result<PathBuf, Error> {
+    fn root_path() -> Result<PathBuf, Error> {
         Ok(dirs::cache_dir().and_then(|mut d | {
             d.push("absentis");
             Some(d)
         }).ok_or(ErrorKind::Cache)?)
     }
 
-    fn dir_exists() -> Result<bool, Error> {
-        Ok(Self::dir_path()?.is_dir())
+    /// the cache directory of `chain`
+    fn dir_path(chain: u64) -> Result<PathBuf, Error> {
+        let mut dir = Self::root_path()?;
+        dir.push(format!("chain_{}", chain));
+        Ok(dir)
+    }
+
+    crate fn store_path(chain: u64) -> Result<PathBuf, Error> {
+        let mut dir = Self::dir_path(chain)?;
+        dir.push("store");
+        Ok(dir)
+    }
+
+    fn dir_exists(chain: u64) -> Result<bool, Error> {
+        Ok(Self::dir_path(chain)?.is_dir())
+    }
+}
+
+/// blocks of `range` not covered by any of `shards`
+fn gaps(range: (u64, u64), shards: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut shards = shards.to_vec();
+    shards.sort();
+    let mut gaps = Vec::new();
+    let mut next = range.0;
+    for (from, to) in shards {
+        if to < next {
+            continue;
+        }
+        if from > range.1 {
+            break;
+        }
+        if from > next {
+            gaps.push((next, from - 1));
+        }
+        next = to.saturating_add(1);
+    }
+    if next <= range.1 {
+        gaps.push((next, range.1));
+    }
+    gaps
+}
+
+/// the span of every run of more than one consecutive or overlapping range of `ranges`
+fn runs(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+    let mut runs: Vec<(u64, u64, usize)> = Vec::new();
+    for (from, to) in ranges {
+        match runs.last_mut() {
+            Some(run) if from <= run.1.saturating_add(1) => {
+                run.1 = std::cmp::max(run.1, to);
+                run.2 += 1;
+            },
+            _ => runs.push((from, to, 1)),
+        }
     }
+    runs.into_iter().filter(|run| run.2 > 1).map(|(from, to, _)| (from, to)).collect()
 }
 
 // TODO: make this a macro #p2
@@ -189,6 +1053,7 @@ impl CacheAction for TxType {
             TxType::Traces(tr) => tr.insert(cache),
             TxType::Logs(logs) => logs.insert(cache),
             TxType::Block(blk) => blk.insert(cache),
+            TxType::Explorer(records) => records.insert(cache),
         }
     }
 
@@ -199,6 +1064,29 @@ impl CacheAction for TxType {
             TxType::Traces(tr) => tr.exists(cache),
             TxType::Logs(logs) => logs.exists(cache),
             TxType::Block(blk) => blk.exists(cache),
+            TxType::Explorer(records) => records.exists(cache),
+        }
+    }
+
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        match self {
+            TxType::Transaction(tx) => tx.matches(cache),
+            TxType::Receipt(rec) => rec.matches(cache),
+            TxType::Traces(tr) => tr.matches(cache),
+            TxType::Logs(logs) => logs.matches(cache),
+            TxType::Block(blk) => blk.matches(cache),
+            TxType::Explorer(records) => records.matches(cache),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TxType::Transaction(tx) => tx.name(),
+            TxType::Receipt(rec) => rec.name(),
+            TxType::Traces(tr) => tr.name(),
+            TxType::Logs(logs) => logs.name(),
+            TxType::Block(blk) => blk.name(),
+            TxType::Explorer(records) => records.name(),
         }
     }
 
@@ -209,6 +1097,7 @@ impl CacheAction for TxType {
             TxType::Traces(tr) => tr.hash(),
             TxType::Logs(logs) => logs.hash(),
             TxType::Block(blk) => blk.hash(),
+            TxType::Explorer(records) => records.hash(),
         }
     }
 
@@ -218,7 +1107,8 @@ impl CacheAction for TxType {
             TxType::Receipt(rec) => rec.empty(),
             TxType::Traces(tr) => tr.empty(),
             TxType::Logs(logs) => logs.empty(),
-            TxType::Block(blk) => blk.empty()
+            TxType::Block(blk) => blk.empty(),
+            TxType::Explorer(records) => records.empty(),
         }
     }
 }
@@ -231,6 +1121,10 @@ pub trait CacheAction {
     fn insert(self, cache: &mut HashMap<H256, Tx>);
     /// checks cache if this type exists within it
     fn exists(&self, cache: &HashMap<H256, Tx>) -> bool;
+    /// checks if the item of this type in cache is equal to this one
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool;
+    /// name of this type, for logging
+    fn name(&self) -> &'static str;
     /// converts CacheAction type to Tx type with all other fields of Tx as `None`
     fn empty(self) -> Tx;
 }
@@ -251,8 +1145,16 @@ impl CacheAction for Block {
         cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional; qed").block.is_some()
     }
 
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.block.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Block"
+    }
+
     fn empty(self) -> Tx {
-        Tx { block: Some(self), transaction: None, logs: None, traces: None, receipt: None }
+        Tx { block: Some(self), transaction: None, logs: None, traces: None, receipt: None, explorer: None }
     }
 }
 
@@ -273,8 +1175,16 @@ impl CacheAction for Transaction {
         cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional; qed").transaction.is_some()
     }
 
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.transaction.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Transaction"
+    }
+
     fn empty(self) -> Tx {
-        Tx { transaction: Some(self), logs: None, traces: None, receipt: None, block: None }
+        Tx { transaction: Some(self), logs: None, traces: None, receipt: None, block: None, explorer: None }
     }
 }
 
@@ -286,6 +1196,7 @@ impl CacheAction for TransactionReceipt {
     fn insert(self, cache: &mut HashMap<H256, Tx>) {
         if cache.contains_key(&self.transaction_hash) {
             let entry = cache.get_mut(&self.transaction_hash).expect("scope is conditional; qed");
+            entry.logs = Some(self.logs.clone());
             entry.receipt = Some(self);
         } else {
             cache.insert(self.hash().clone(), self.empty());
@@ -296,8 +1207,16 @@ impl CacheAction for TransactionReceipt {
         cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional").receipt.is_some()
     }
 
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.receipt.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Receipt"
+    }
+
     fn empty(self) -> Tx {
-        Tx {receipt: Some(self), logs: None, traces: None, transaction: None, block: None}
+        Tx {logs: Some(self.logs.clone()), receipt: Some(self), traces: None, transaction: None, block: None, explorer: None}
     }
 }
 
@@ -325,14 +1244,25 @@ impl CacheAction for Vec<Trace> {
         }
     }
 
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.traces.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Traces"
+    }
+
     fn empty(self) -> Tx {
-        Tx {traces: Some(self), transaction: None, logs: None, receipt: None, block: None}
+        Tx {traces: Some(self), transaction: None, logs: None, receipt: None, block: None, explorer: None}
     }
 }
 
-impl CacheAction for Log {
+impl CacheAction for Vec<Log> {
     fn hash(&self) -> &H256 {
-        self.transaction_hash.as_ref().expect("Transaction hash cannot be empty")
+        self.get(0).as_ref()
+            .expect(&verb_msg!("Cannot insert an empty vector!"))
+            .transaction_hash.as_ref()
+            .expect("Transaction hash cannot be empty")
     }
 
     fn insert(self, cache: &mut HashMap<H256, Tx>) {
@@ -345,10 +1275,62 @@ impl CacheAction for Log {
     }
 
     fn exists(&self, cache: &HashMap<H256, Tx>) -> bool {
-        cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional; qed").logs.is_some()
+        if self.is_empty() {
+            false
+        } else {
+            cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional; qed").logs.is_some()
+        }
+    }
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.logs.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Logs"
+    }
+
+    fn empty(self) -> Tx {
+        Tx { logs: Some(self), transaction: None, traces: None, receipt: None, block: None, explorer: None}
+    }
+}
+
+impl CacheAction for Vec<EtherScanRecord> {
+    fn hash(&self) -> &H256 {
+        match self.get(0).expect(&verb_msg!("Cannot insert an empty vector!")) {
+            EtherScanRecord::Normal(tx) => &tx.hash,
+            EtherScanRecord::Internal(tx) => &tx.hash,
+            EtherScanRecord::Token(tx) => &tx.hash,
+            EtherScanRecord::Nft(tx) => &tx.hash,
+        }
+    }
+
+    fn insert(self, cache: &mut HashMap<H256, Tx>) {
+        if cache.contains_key(self.hash()) {
+            let entry = cache.get_mut(self.hash()).expect("scope is conditional; qed");
+            entry.explorer = Some(self);
+        } else {
+            cache.insert(self.hash().clone(), self.empty());
+        }
+    }
+
+    fn exists(&self, cache: &HashMap<H256, Tx>) -> bool {
+        if self.is_empty() {
+            false
+        } else {
+            cache.contains_key(self.hash()) && cache.get(self.hash()).expect("scope is conditional; qed").explorer.is_some()
+        }
+    }
+
+    fn matches(&self, cache: &HashMap<H256, Tx>) -> bool {
+        cache.get(self.hash()).and_then(|entry| entry.explorer.as_ref()) == Some(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "Explorer Records"
     }
+
     fn empty(self) -> Tx {
-        Tx { logs: Some(self), transaction: None, traces: None, receipt: None, block: None}
+        Tx { explorer: Some(self), transaction: None, logs: None, traces: None, receipt: None, block: None }
     }
 }
 impl From<Block> for TxType {
@@ -375,9 +1357,15 @@ impl From<Vec<Trace>> for TxType {
     }
 }
 
-impl From<Log> for TxType {
-    fn from(log: Log) -> TxType {
-        TxType::Logs(log)
+impl From<Vec<Log>> for TxType {
+    fn from(logs: Vec<Log>) -> TxType {
+        TxType::Logs(logs)
+    }
+}
+
+impl From<Vec<EtherScanRecord>> for TxType {
+    fn from(records: Vec<EtherScanRecord>) -> TxType {
+        TxType::Explorer(records)
     }
 }
 
@@ -389,8 +1377,108 @@ mod test {
     #[test]
     fn get_blocknumber() {
         pretty_env_logger::try_init();
-        let cache = TransactionCache::new(Address::from("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"), BlockNumber::Earliest, BlockNumber::Number(6000000)).unwrap();
-        let tx = cache.tx_by_blocknum_index(1165405, 2);
+        let cache = TransactionCache::new(LEGACY_CHAIN, Address::from("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"), 0, 6_000_000).unwrap();
+        let tx = cache.tx_by_blocknum_index(1165405, 2).unwrap();
         info!("TX: {:?}", tx);
     }
+
+    #[test]
+    fn it_should_parse_cache_ranges() {
+        let addr = Address::from("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359");
+        let name = TransactionCache::name(&addr, 0, 6_000_000);
+        assert_eq!(TransactionCache::parse_range(&addr, &name), Some((0, 6_000_000)));
+        assert_eq!(TransactionCache::parse_range(&addr, "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359_earliest_latest.bin"), None);
+        assert_eq!(TransactionCache::parse_range(&Address::zero(), &name), None);
+        assert_eq!(TransactionCache::parse_name(&name), Some((addr, 0, 6_000_000)));
+        assert_eq!(TransactionCache::parse_name("0x1234_0_1.bin"), None);
+    }
+
+    #[test]
+    fn it_should_parse_chain_dirs() {
+        assert_eq!(TransactionCache::parse_chain("chain_1"), Some(1));
+        assert_eq!(TransactionCache::parse_chain("chain_3"), Some(3));
+        assert_eq!(TransactionCache::parse_chain("store"), None);
+        assert_eq!(TransactionCache::parse_chain("chain_"), None);
+    }
+
+    #[test]
+    fn it_should_tell_shard_formats_apart() {
+        let path = PathBuf::from("/tmp/absentis_shard_formats");
+        let db = SimpleDB::<ShardData>::new(path.clone()).unwrap();
+        let complete = Completeness { transaction: true, receipt: true, traces: true, block: false };
+        let entries = ShardData::Entries(vec![(H256::zero(), Entry { position: Some((1, 2)), complete })]);
+        db.save(entries.clone()).unwrap();
+        assert_eq!(db.get().unwrap(), entries);
+        assert_eq!(entries.incomplete(), Some(1));
+        let positions = ShardData::Positions(vec![(H256::zero(), Some((1, 2)))]);
+        db.save(positions.clone()).unwrap();
+        assert_eq!(db.get().unwrap(), positions);
+        assert_eq!(positions.incomplete(), None);
+        let rewards = ShardData::Rewards {
+            entries: vec![(H256::zero(), Entry { position: Some((1, 2)), complete })],
+            rewards: vec![Reward { block: 1, kind: RewardKind::Uncle }],
+        };
+        db.save(rewards.clone()).unwrap();
+        assert_eq!(db.get().unwrap(), rewards);
+        assert_eq!(rewards.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn transaction(index: u64) -> Transaction {
+        let json = format!(r#"{{"hash":"0x0000000000000000000000000000000000000000000000000000000000000001","nonce":"0x0",
+            "blockHash":"0x0000000000000000000000000000000000000000000000000000000000000002","blockNumber":"0x1e8480",
+            "transactionIndex":"0x{:x}","from":"0x0000000000000000000000000000000000000003",
+            "to":"0x0000000000000000000000000000000000000004","value":"0x0","gasPrice":"0x0","gas":"0x0","input":"0x"}}"#, index);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn it_should_let_the_second_record_win_when_overwriting() {
+        const CHAIN: u64 = 9_000_014;
+        let hash = H256::from(1);
+        let mut cache = TransactionCache::scratch(CHAIN, Address::zero());
+        cache.set_merge_policy(MergePolicy::Overwrite);
+        cache.insert(TxType::from(transaction(0))).unwrap();
+        cache.insert(TxType::from(transaction(1))).unwrap();
+        assert_eq!(cache.txhash_by_blocknum_index(2_000_000, 1), Some(hash));
+        // once flushed, the record is replaced in the store
+        cache.flush().unwrap();
+        cache.insert(TxType::from(transaction(2))).unwrap();
+        let tx = cache.get(&hash).unwrap().unwrap();
+        assert_eq!(tx.position(), Some((2_000_000, 2)));
+        assert_eq!(cache.txhash_by_blocknum_index(2_000_000, 1), None);
+        assert_eq!(cache.txhash_by_blocknum_index(2_000_000, 2), Some(hash));
+
+        cache.set_merge_policy(MergePolicy::VerifyEqual);
+        assert!(cache.insert(TxType::from(transaction(2))).is_ok());
+        assert!(cache.insert(TxType::from(transaction(3))).is_err());
+        TransactionCache::remove_scratch(CHAIN);
+    }
+
+    #[test]
+    fn it_should_split_checkpoints_into_chunks() {
+        let hashes = (0..5).map(|n| (H256::from(n as u64), n)).collect::<Vec<_>>();
+        let checkpoint = Checkpoint { to: 4, sources: vec!["EtherScan".to_string()], hashes, chunk: 2, done: 0 };
+        assert_eq!(checkpoint.chunks(), 3);
+        assert_eq!(checkpoint.chunk(1), &checkpoint.hashes[2..4]);
+        assert_eq!(checkpoint.chunk(2), &checkpoint.hashes[4..]);
+        assert!(checkpoint.chunk(3).is_empty());
+    }
+
+    #[test]
+    fn it_should_find_gaps_between_shards() {
+        assert_eq!(gaps((0, 2_000_000), &[]), vec![(0, 2_000_000)]);
+        assert_eq!(gaps((0, 2_000_000), &[(1_000_001, 2_000_000), (0, 1_000_000)]), vec![]);
+        assert_eq!(gaps((500, 3_000), &[(0, 1_000), (2_000, 2_500)]), vec![(1_001, 1_999), (2_501, 3_000)]);
+        assert_eq!(gaps((0, 100), &[(50, 200)]), vec![(0, 49)]);
+    }
+
+    #[test]
+    fn it_should_compact_runs_of_shards() {
+        assert_eq!(runs(vec![]), vec![]);
+        assert_eq!(runs(vec![(0, 1_000)]), vec![]);
+        assert_eq!(runs(vec![(1_001, 2_000), (0, 1_000), (5_000, 6_000)]), vec![(0, 2_000)]);
+        assert_eq!(runs(vec![(0, 1_000), (500, 1_500), (3_000, 4_000), (4_001, 4_500)]), vec![(0, 1_500), (3_000, 4_500)]);
+        assert_eq!(runs(vec![(0, 1_000), (1_002, 2_000)]), vec![]);
+    }
 }
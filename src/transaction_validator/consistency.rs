@@ -0,0 +1,123 @@
+//! Cross-checks what EtherScan listed for a transaction against what the node returned for it.
+//! A disagreement is a bug in the explorer, or in the node
+use web3::types::{Address, Action, Res, H256, U256};
+use crate::etherscan::{EtherScanRecord, EtherScanTx, EtherScanInternalTx};
+
+use super::cache::Tx;
+
+/// a field EtherScan and the node disagree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disagreement {
+    pub hash: H256,
+    pub field: &'static str,
+    /// what EtherScan listed
+    pub explorer: String,
+    /// what the node returned
+    pub node: String,
+}
+
+impl std::fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} of {:x}: EtherScan has {}, node has {}", self.field, self.hash, self.explorer, self.node)
+    }
+}
+
+/// every disagreement between the EtherScan records of the transaction `hash` and what the node returned for it.
+/// Parts of `tx` that were not fetched are not checked
+crate fn check(hash: H256, tx: &Tx) -> Vec<Disagreement> {
+    let mut found = Vec::new();
+    for record in tx.explorer.iter().flatten() {
+        match record {
+            EtherScanRecord::Normal(record) => check_normal(hash, record, tx, &mut found),
+            EtherScanRecord::Internal(record) => check_internal(hash, record, tx, &mut found),
+            // token transfers are read from logs, which are checked through the location of an entry
+            EtherScanRecord::Token(_) | EtherScanRecord::Nft(_) => (),
+        }
+    }
+    found
+}
+
+fn check_normal(hash: H256, record: &EtherScanTx, tx: &Tx, found: &mut Vec<Disagreement>) {
+    let mut differs = |field, explorer: String, node: String| {
+        if explorer != node {
+            found.push(Disagreement { hash, field, explorer, node });
+        }
+    };
+    if let Some(transaction) = tx.transaction.as_ref() {
+        differs("value", record.value.to_string(), transaction.value.to_string());
+    }
+    if let Some(receipt) = tx.receipt.as_ref() {
+        if let Some(gas_used) = receipt.gas_used {
+            differs("gas used", record.gas_used.to_string(), gas_used.to_string());
+        }
+        // receipts before Byzantium have no status
+        if let Some(status) = receipt.status {
+            differs("error status", record.is_error.to_string(), (status.as_u64() == 0).to_string());
+        }
+        differs("contract address", address(record.contract_address), address(receipt.contract_address));
+    }
+}
+
+/// an internal transaction has no hash of its own, so it is matched to the traces that move ether
+/// between the same accounts
+fn check_internal(hash: H256, record: &EtherScanInternalTx, tx: &Tx, found: &mut Vec<Disagreement>) {
+    let traces = match tx.traces.as_ref() {
+        Some(traces) => traces,
+        None => return,
+    };
+    // self-destructs and rewards have no counterpart we can match reliably
+    if record.tx_type != "call" && record.tx_type != "create" {
+        return;
+    }
+    let to = record.to.or(record.contract_address);
+    let values = traces.iter()
+        .filter_map(|trace| match (&trace.action, trace.result.as_ref()) {
+            (Action::Call(call), _) => Some((call.from, Some(call.to), call.value)),
+            (Action::Create(create), Some(Res::Create(res))) => Some((create.from, Some(res.address), create.value)),
+            _ => None,
+        })
+        .filter(|(from, trace_to, _)| *from == record.from && *trace_to == to)
+        .map(|(_, _, value)| value)
+        .collect::<Vec<U256>>();
+    if values.is_empty() {
+        found.push(Disagreement {
+            field: "internal transaction",
+            explorer: format!("{} from {} to {}", record.value, address(Some(record.from)), address(to)),
+            node: "no trace between them".to_string(),
+            hash,
+        });
+    } else if !values.contains(&record.value) {
+        found.push(Disagreement {
+            field: "internal value",
+            explorer: record.value.to_string(),
+            node: values.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(" or "),
+            hash,
+        });
+    }
+}
+
+fn address(addr: Option<Address>) -> String {
+    addr.map(|a| format!("0x{:x}", a)).unwrap_or_else(|| "none".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_only_check_what_was_fetched() {
+        let json = r#"{"blockNumber":"2000000","timeStamp":"1470173578","hash":"0x8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a2",
+            "from":"0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359","to":"0x0000000000000000000000000000000000000001",
+            "value":"1","contractAddress":"","input":"","type":"call","gas":"2300","gasUsed":"0","traceId":"0",
+            "isError":"0","errCode":""}"#;
+        let record = serde_json::from_str(json).unwrap();
+        let mut tx = Tx { transaction: None, receipt: None, traces: None, logs: None, block: None, explorer: None };
+        tx.explorer = Some(vec![EtherScanRecord::Internal(record)]);
+        assert!(check(H256::zero(), &tx).is_empty());
+
+        tx.traces = Some(Vec::new());
+        let found = check(H256::zero(), &tx);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].field, "internal transaction");
+    }
+}
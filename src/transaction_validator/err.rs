@@ -1,4 +1,5 @@
 use failure::Fail;
+use web3::types::H256;
 
 #[derive(Debug, Fail)]
 pub enum CacheError {
@@ -6,6 +7,8 @@ pub enum CacheError {
     NotFound(String),
     #[fail(display = "IO Error, {}", _0)]
     IO(#[fail(cause)] std::io::Error),
+    #[fail(display = "{} of {:x} conflicts with the one already cached", _0, _1)]
+    Conflict(&'static str, H256),
 }
 
 impl From<std::io::Error> for CacheError {
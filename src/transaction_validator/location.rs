@@ -0,0 +1,363 @@
+//! Typed representation of the `location` column of a QuickBlocks CSV
+//! and the places an address may appear within a cached transaction
+use serde::de::{self, Deserialize, Deserializer};
+use web3::types::{Address, H256, Action, Res};
+use std::str::FromStr;
+use crate::err::ValidateMsg;
+
+use super::{
+    scan_bytes,
+    cache::{Tx, RewardKind},
+};
+
+/// transaction index QuickBlocks lists the reward of a mined block at
+crate const MINER_INDEX: usize = 99_999;
+/// transaction index QuickBlocks lists the reward of a mined uncle at
+crate const UNCLE_INDEX: usize = 99_998;
+
+impl RewardKind {
+    /// transaction index QuickBlocks lists this reward at
+    crate fn index(self) -> usize {
+        match self {
+            RewardKind::Miner => MINER_INDEX,
+            RewardKind::Uncle => UNCLE_INDEX,
+        }
+    }
+}
+
+/// where in a transaction an address appears
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// sender of the transaction
+    From,
+    /// recipient of the transaction
+    To,
+    /// somewhere in the input data of the transaction
+    Input,
+    /// contract created by the transaction
+    Creation,
+    /// author of the block. A block-level appearance, listed at `MINER_INDEX`
+    Miner,
+    /// author of an uncle of the block. A block-level appearance, listed at `UNCLE_INDEX`
+    Uncle,
+    /// an action of the trace at `index`. `trace_address` is the path QuickBlocks lists: that of the parent trace
+    Trace { index: usize, trace_address: Vec<usize>, field: TraceField },
+    /// a part of the log at `index` of the receipt
+    Log { index: usize, field: LogField },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceField {
+    From,
+    To,
+    Input,
+    Creation,
+    RefundAddr,
+    SelfDestruct,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogField {
+    /// the contract that emitted the log
+    Generator,
+    Topic(usize),
+    Data,
+}
+
+impl Location {
+    /// every location `addr` appears at in `tx`
+    crate fn appearances(addr: &Address, tx: &Tx) -> Vec<Location> {
+        let mut locations = Vec::new();
+        if let Some(transaction) = tx.transaction.as_ref() {
+            if transaction.from == *addr {
+                locations.push(Location::From);
+            }
+            if transaction.to.as_ref() == Some(addr) {
+                locations.push(Location::To);
+            }
+            if scan_bytes(*addr, &transaction.input.0) {
+                locations.push(Location::Input);
+            }
+        }
+
+        if let Some(receipt) = tx.receipt.as_ref() {
+            if receipt.contract_address.as_ref() == Some(addr) {
+                locations.push(Location::Creation);
+            }
+        }
+
+        if let Some(logs) = tx.logs.as_ref() {
+            for (index, log) in logs.iter().enumerate() {
+                if log.address == *addr {
+                    locations.push(Location::Log { index, field: LogField::Generator });
+                }
+                for (topic, hash) in log.topics.iter().enumerate() {
+                    if topic_has(addr, hash) {
+                        locations.push(Location::Log { index, field: LogField::Topic(topic) });
+                    }
+                }
+                if scan_bytes(*addr, &log.data.0) {
+                    locations.push(Location::Log { index, field: LogField::Data });
+                }
+            }
+        }
+
+        if let Some(traces) = tx.traces.as_ref() {
+            for (index, trace) in traces.iter().enumerate() {
+                let trace_loc = |field| Location::Trace { index, trace_address: listed_path(&trace.trace_address), field };
+                match &trace.action {
+                    Action::Call(call) => {
+                        if call.from == *addr { locations.push(trace_loc(TraceField::From)) }
+                        if call.to == *addr { locations.push(trace_loc(TraceField::To)) }
+                        if scan_bytes(*addr, &call.input.0) { locations.push(trace_loc(TraceField::Input)) }
+                    },
+                    Action::Create(create) => {
+                        if create.from == *addr { locations.push(trace_loc(TraceField::From)) }
+                        if scan_bytes(*addr, &create.init.0) { locations.push(trace_loc(TraceField::Input)) }
+                        if let Some(Res::Create(res)) = trace.result.as_ref() {
+                            if res.address == *addr { locations.push(trace_loc(TraceField::Creation)) }
+                        }
+                    },
+                    Action::Suicide(suicide) => {
+                        if suicide.address == *addr { locations.push(trace_loc(TraceField::SelfDestruct)) }
+                        if suicide.refund_address == *addr { locations.push(trace_loc(TraceField::RefundAddr)) }
+                    },
+                    // block rewards are checked through the rewards of the cache
+                    _ => (),
+                }
+            }
+        }
+        locations
+    }
+
+    /// whether `claimed`, a location read from a CSV, names this location.
+    /// The path of a trace is only compared when the CSV gives one
+    crate fn matches(&self, claimed: &Location) -> bool {
+        match (self, claimed) {
+            (Location::Trace { index, trace_address, field },
+             Location::Trace { index: claimed_index, trace_address: claimed_address, field: claimed_field }) => {
+                index == claimed_index && field == claimed_field
+                    && (claimed_address.is_empty() || trace_address == claimed_address)
+            },
+            _ => self == claimed,
+        }
+    }
+
+    /// the reward this location stands for, if it is a block-level appearance
+    crate fn reward(&self) -> Option<RewardKind> {
+        match self {
+            Location::Miner => Some(RewardKind::Miner),
+            Location::Uncle => Some(RewardKind::Uncle),
+            _ => None,
+        }
+    }
+
+    /// whether the part of `tx` this location points into has been fetched.
+    /// A location cannot be verified against data we do not have
+    crate fn verifiable(&self, tx: &Tx) -> bool {
+        match self {
+            Location::From | Location::To | Location::Input => tx.transaction.is_some(),
+            Location::Creation => tx.receipt.is_some(),
+            Location::Log { .. } => tx.logs.is_some(),
+            Location::Trace { .. } => tx.traces.is_some(),
+            // not part of a transaction
+            Location::Miner | Location::Uncle => false,
+        }
+    }
+}
+
+/// the path QuickBlocks lists a trace at: the trace address of its parent.
+/// Top-level traces and their direct children are listed without one
+fn listed_path(trace_address: &[usize]) -> Vec<usize> {
+    trace_address.split_last().map(|(_, parent)| parent.to_vec()).unwrap_or_default()
+}
+
+/// the topic an indexed address is logged as. Topics hold addresses left-padded to 32 bytes
+crate fn address_topic(addr: &Address) -> H256 {
+    let mut padded = [0u8; 32];
+    padded[12..].copy_from_slice(&addr[..]);
+    H256::from(padded)
+}
+
+fn topic_has(addr: &Address, topic: &H256) -> bool {
+    *topic == address_topic(addr)
+}
+
+impl FromStr for Location {
+    type Err = ValidateMsg;
+
+    fn from_str(s: &str) -> Result<Location, ValidateMsg> {
+        let invalid = || ValidateMsg::InvalidLocation(s.to_string());
+        match s {
+            "from" => return Ok(Location::From),
+            "to" => return Ok(Location::To),
+            "input" => return Ok(Location::Input),
+            "creation" => return Ok(Location::Creation),
+            "miner" => return Ok(Location::Miner),
+            "uncle" => return Ok(Location::Uncle),
+            _ => (),
+        }
+
+        if s.starts_with("trace_") {
+            // trace_{index}_[{trace_address}]_{field}, trace address is optional
+            let rest = &s["trace_".len()..];
+            let (index, rest) = split_index(rest).ok_or_else(invalid)?;
+            let (trace_address, field) = if rest.starts_with('[') {
+                let end = rest.find(']').ok_or_else(invalid)?;
+                let addr = rest[1..end]
+                    .split('_')
+                    .map(|n| n.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|_| invalid())?;
+                if !rest[end + 1..].starts_with('_') {
+                    return Err(invalid());
+                }
+                (addr, &rest[end + 2..])
+            } else {
+                (Vec::new(), rest)
+            };
+            let field = match field {
+                "from" => TraceField::From,
+                "to" => TraceField::To,
+                "input" => TraceField::Input,
+                "creation" => TraceField::Creation,
+                "refundAddr" => TraceField::RefundAddr,
+                "self-destruct" => TraceField::SelfDestruct,
+                _ => return Err(invalid()),
+            };
+            Ok(Location::Trace { index, trace_address, field })
+        } else if s.starts_with("log_") {
+            // log_{index}_{field}
+            let (index, field) = split_index(&s["log_".len()..]).ok_or_else(invalid)?;
+            let field = match field {
+                "generator" => LogField::Generator,
+                "data" => LogField::Data,
+                f if f.starts_with("topic_") => LogField::Topic(f["topic_".len()..].parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            };
+            Ok(Location::Log { index, field })
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+/// splits `{num}_{rest}` into its number and the rest
+fn split_index(s: &str) -> Option<(usize, &str)> {
+    let pos = s.find('_')?;
+    Some((s[..pos].parse().ok()?, &s[pos + 1..]))
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Location::From => write!(f, "from"),
+            Location::To => write!(f, "to"),
+            Location::Input => write!(f, "input"),
+            Location::Creation => write!(f, "creation"),
+            Location::Miner => write!(f, "miner"),
+            Location::Uncle => write!(f, "uncle"),
+            Location::Trace { index, trace_address, field } => {
+                write!(f, "trace_{}_", index)?;
+                if !trace_address.is_empty() {
+                    let addr = trace_address.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+                    write!(f, "[{}]_", addr.join("_"))?;
+                }
+                write!(f, "{}", field)
+            },
+            Location::Log { index, field } => write!(f, "log_{}_{}", index, field),
+        }
+    }
+}
+
+impl std::fmt::Display for TraceField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TraceField::From => write!(f, "from"),
+            TraceField::To => write!(f, "to"),
+            TraceField::Input => write!(f, "input"),
+            TraceField::Creation => write!(f, "creation"),
+            TraceField::RefundAddr => write!(f, "refundAddr"),
+            TraceField::SelfDestruct => write!(f, "self-destruct"),
+        }
+    }
+}
+
+impl std::fmt::Display for LogField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogField::Generator => write!(f, "generator"),
+            LogField::Topic(n) => write!(f, "topic_{}", n),
+            LogField::Data => write!(f, "data"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Location {
+    fn deserialize<D>(deserializer: D) -> Result<Location, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        Location::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::Trace;
+
+    #[test]
+    fn it_should_parse_locations() {
+        assert_eq!(Location::from_str("creation").unwrap(), Location::Creation);
+        assert_eq!(Location::from_str("trace_0_self-destruct").unwrap(),
+                   Location::Trace { index: 0, trace_address: vec![], field: TraceField::SelfDestruct });
+        assert_eq!(Location::from_str("trace_3_[0_0]_from").unwrap(),
+                   Location::Trace { index: 3, trace_address: vec![0, 0], field: TraceField::From });
+        assert_eq!(Location::from_str("log_28_topic_2").unwrap(),
+                   Location::Log { index: 28, field: LogField::Topic(2) });
+        assert!(Location::from_str("trace_x_to").is_err());
+        assert!(Location::from_str("log_1_topic").is_err());
+    }
+
+    fn trace(trace_address: &str, from: &str, to: &str) -> Trace {
+        let json = format!(r#"{{"action":{{"callType":"call","from":"{}","gas":"0x0","input":"0x","to":"{}","value":"0x0"}},
+            "blockHash":"0x0000000000000000000000000000000000000000000000000000000000000001","blockNumber":2000000,
+            "result":{{"gasUsed":"0x0","output":"0x"}},"subtraces":0,"traceAddress":{},
+            "transactionHash":"0x0000000000000000000000000000000000000000000000000000000000000002",
+            "transactionPosition":0,"type":"call"}}"#, from, to, trace_address);
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn it_should_find_traces_at_quickblocks_paths() {
+        let addr = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+        let other = "0x0000000000000000000000000000000000000001";
+        let tx = Tx {
+            traces: Some(vec![trace("[]", other, other), trace("[0]", other, addr), trace("[0,0]", addr, other)]),
+            transaction: None, receipt: None, logs: None, block: None, explorer: None,
+        };
+        let expected = Location::appearances(&Address::from(addr), &tx);
+        assert_eq!(expected, vec![
+            Location::Trace { index: 1, trace_address: vec![], field: TraceField::To },
+            Location::Trace { index: 2, trace_address: vec![0], field: TraceField::From },
+        ]);
+        let found = |claimed: &str| {
+            let claimed = Location::from_str(claimed).unwrap();
+            expected.iter().any(|l| l.matches(&claimed))
+        };
+        assert!(found("trace_1_to"));
+        assert!(found("trace_2_[0]_from"));
+        assert!(found("trace_2_from"));
+        assert!(!found("trace_2_[1]_from"));
+        assert!(!found("trace_1_from"));
+    }
+
+    #[test]
+    fn it_should_display_like_quickblocks() {
+        for loc in &["from", "input", "miner", "uncle", "trace_2_[0]_to", "trace_120_[69]_input", "trace_1_refundAddr", "log_0_generator", "log_1_data"] {
+            assert_eq!(&Location::from_str(loc).unwrap().to_string(), loc);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Places that list the transactions an address appears in.
+//! No source is complete on its own: explorers skip addresses that only appear in input data,
+//! `trace_filter` only sees calls, and logs only see events. The validator merges what every source lists
+use log::*;
+use failure::{Error, ResultExt};
+use futures::future;
+use itertools::Itertools;
+use web3::{
+    BatchTransport,
+    types::{BlockNumber, FilterBuilder, TraceFilterBuilder, TransactionId, H160, H256},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str::FromStr,
+};
+use crate::{
+    utils,
+    client::Client,
+    etherscan::{EtherScan, EtherScanRecord, SortType, BlockType},
+    err::{ErrorKind, ValidateMsg},
+};
+
+use super::{
+    location,
+    cache::{Reward, RewardKind},
+};
+
+/// blocks asked for in one `trace_filter` or `eth_getLogs` request. Nodes time out on larger ranges
+const NODE_CHUNK: u64 = 10_000;
+
+/// a transaction a source lists for an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Appearance {
+    pub hash: H256,
+    pub block: u64,
+    /// what an explorer listed for the transaction. Kept with the cached transaction
+    pub record: Option<EtherScanRecord>,
+}
+
+/// lists candidate transactions of an address. Every candidate is fetched from the node,
+/// so a source may list transactions the address does not appear in
+pub trait AppearanceSource<T: BatchTransport> {
+    /// name of the source, for logs
+    fn name(&self) -> String;
+
+    /// what this source lists, recorded with the cache shards it listed transactions for.
+    /// A shard is only reused for sources that listed it
+    fn lists(&self) -> Vec<String> {
+        vec![self.name()]
+    }
+
+    /// every transaction this source lists for `addr` in blocks `from..=to`
+    fn appearances(&self, client: &mut Client<T>, addr: H160, from: u64, to: u64) -> Result<Vec<Appearance>, Error>;
+
+    /// whether this source lists the blocks and uncles an address mined
+    fn lists_rewards(&self) -> bool {
+        false
+    }
+
+    /// every block and uncle `addr` mined in blocks `from..=to`. Only asked of sources that `lists_rewards`
+    fn rewards(&self, _client: &mut Client<T>, _addr: H160, _from: u64, _to: u64) -> Result<Vec<Reward>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+impl<T> AppearanceSource<T> for EtherScan where T: BatchTransport + Send + Sync + 'static {
+    fn name(&self) -> String {
+        self.explorer().to_string()
+    }
+
+    /// an explorer listing token transfers lists other transactions than one that does not,
+    /// so a cache listed with them is not reused for a validation without them, or the other way around
+    fn lists(&self) -> Vec<String> {
+        if self.lists_token_transfers() {
+            vec![format!("{} with token transfers", self.explorer())]
+        } else {
+            vec![self.explorer().to_string()]
+        }
+    }
+
+    fn appearances(&self, client: &mut Client<T>, addr: H160, from: u64, to: u64) -> Result<Vec<Appearance>, Error> {
+        let records = self.get_tx_by_account(client.ev_loop(), addr, from, to, SortType::Ascending)?;
+        Ok(records.into_iter()
+            .map(|record| Appearance { hash: record.hash(), block: record.block_number(), record: Some(record) })
+            .collect())
+    }
+
+    fn lists_rewards(&self) -> bool {
+        true
+    }
+
+    fn rewards(&self, client: &mut Client<T>, addr: H160, from: u64, to: u64) -> Result<Vec<Reward>, Error> {
+        Ok(self.get_mined_blocks(client.ev_loop(), addr, from, to)?
+            .into_iter()
+            .map(|(block, block_type)| match block_type {
+                BlockType::Blocks => Reward { block, kind: RewardKind::Miner },
+                BlockType::Uncles => Reward { block, kind: RewardKind::Uncle },
+            })
+            .collect())
+    }
+}
+
+/// transactions with a trace from or to the address, found with `trace_filter`.
+/// Needs a node with tracing enabled (Parity/OpenEthereum with `--tracing on`)
+#[derive(Debug, Clone, Default)]
+pub struct NodeTraces;
+
+impl<T> AppearanceSource<T> for NodeTraces where T: BatchTransport + Send + Sync + 'static {
+    fn name(&self) -> String {
+        "trace_filter".to_string()
+    }
+
+    fn appearances(&self, client: &mut Client<T>, addr: H160, from: u64, to: u64) -> Result<Vec<Appearance>, Error> {
+        let mut found = Vec::new();
+        for (start, end) in chunks(from, to, NODE_CHUNK) {
+            if utils::interrupted() {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            // `trace_filter` matches traces from any of `from_address` AND to any of `to_address`,
+            // so each side is asked for on its own
+            let range = || TraceFilterBuilder::default()
+                .from_block(BlockNumber::Number(start))
+                .to_block(BlockNumber::Number(end));
+            for filter in vec![range().from_address(vec![addr]).build(), range().to_address(vec![addr]).build()] {
+                let fut = client.web3.trace().filter(filter);
+                let traces = client.run(fut).map_err(|e| ErrorKind::Network(format!("{}", e)))?;
+                // rewards have no transaction
+                found.extend(traces.into_iter().filter_map(|trace| {
+                    Some(Appearance { hash: trace.transaction_hash?, block: trace.block_number, record: None })
+                }));
+            }
+            debug!("Filtered traces of blocks {}..{}", start, end);
+        }
+        Ok(found)
+    }
+}
+
+/// transactions with a log emitted by the address, or with the address as an indexed topic.
+/// Finds token transfers without an explorer
+#[derive(Debug, Clone, Default)]
+pub struct NodeLogs;
+
+impl<T> AppearanceSource<T> for NodeLogs where T: BatchTransport + Send + Sync + 'static {
+    fn name(&self) -> String {
+        "eth_getLogs".to_string()
+    }
+
+    fn appearances(&self, client: &mut Client<T>, addr: H160, from: u64, to: u64) -> Result<Vec<Appearance>, Error> {
+        let topic = Some(vec![location::address_topic(&addr)]);
+
+        let mut found = Vec::new();
+        for (start, end) in chunks(from, to, NODE_CHUNK) {
+            if utils::interrupted() {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let range = || FilterBuilder::default()
+                .from_block(BlockNumber::Number(start))
+                .to_block(BlockNumber::Number(end));
+            // the first topic is the event signature; the address may be any of the others
+            let filters = vec![
+                range().address(vec![addr]).build(),
+                range().topics(None, topic.clone(), None, None).build(),
+                range().topics(None, None, topic.clone(), None).build(),
+                range().topics(None, None, None, topic.clone()).build(),
+            ];
+            for filter in filters {
+                let fut = client.web3.eth().logs(filter);
+                let logs = client.run(fut).map_err(|e| ErrorKind::Network(format!("{}", e)))?;
+                // pending logs have neither
+                found.extend(logs.into_iter().filter_map(|log| {
+                    Some(Appearance { hash: log.transaction_hash?, block: log.block_number?.as_u64(), record: None })
+                }));
+            }
+            debug!("Filtered logs of blocks {}..{}", start, end);
+        }
+        Ok(found)
+    }
+}
+
+/// transaction hashes read from a file, one per line, optionally followed by their block: `0x<hash>[,<block>]`.
+/// Blank lines and lines starting with `#` are skipped. Blocks that are not given are asked of the node
+#[derive(Debug, Clone)]
+pub struct HashFile {
+    path: PathBuf,
+}
+
+impl HashFile {
+    pub fn new(path: PathBuf) -> Self {
+        HashFile { path }
+    }
+
+    /// the hashes of the file, with their block if one is given
+    fn read(&self) -> Result<Vec<(H256, Option<u64>)>, Error> {
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|e| format!("Could not read hash file {}: {}", self.path.display(), e))?;
+        Ok(parse_hashes(&contents)?)
+    }
+}
+
+impl<T> AppearanceSource<T> for HashFile where T: BatchTransport + Send + Sync + 'static {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn appearances(&self, client: &mut Client<T>, _addr: H160, from: u64, to: u64) -> Result<Vec<Appearance>, Error> {
+        let (known, unknown): (Vec<_>, Vec<_>) = self.read()?.into_iter().partition(|(_, block)| block.is_some());
+        let mut found = known.into_iter()
+            .map(|(hash, block)| (hash, block.expect("partitioned on block; qed")))
+            .collect::<Vec<(H256, u64)>>();
+        if !unknown.is_empty() {
+            info!("Looking up the blocks of {} transactions in {}", unknown.len(), self.path.display());
+            let eth = client.web3.eth();
+            let lookups = unknown.iter().map(|(hash, _)| eth.transaction(TransactionId::Hash(*hash))).collect::<Vec<_>>();
+            let txs = client.run(future::join_all(lookups)).map_err(|e| ErrorKind::Network(format!("{}", e)))?;
+            for ((hash, _), tx) in unknown.into_iter().zip(txs) {
+                match tx.and_then(|tx| tx.block_number) {
+                    Some(block) => found.push((hash, block.as_u64())),
+                    None => warn!("Transaction {:x} of {} is not on chain, or is pending", hash, self.path.display()),
+                }
+            }
+        }
+        Ok(found.into_iter()
+            .filter(|(_, block)| *block >= from && *block <= to)
+            .map(|(hash, block)| Appearance { hash, block, record: None })
+            .collect())
+    }
+}
+
+fn parse_hashes(contents: &str) -> Result<Vec<(H256, Option<u64>)>, ValidateMsg> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let invalid = || ValidateMsg::InvalidHashFile(line.to_string());
+            let mut fields = line.split(',').map(str::trim);
+            let hash = fields.next().ok_or_else(invalid)?;
+            let hash = H256::from_str(hash.trim_start_matches("0x")).map_err(|_| invalid())?;
+            let block = fields.next().map(|b| b.parse::<u64>()).transpose().map_err(|_| invalid())?;
+            if fields.next().is_some() {
+                return Err(invalid());
+            }
+            Ok((hash, block))
+        })
+        .collect()
+}
+
+/// splits `from..=to` into ranges of at most `size` blocks
+fn chunks(from: u64, to: u64, size: u64) -> Vec<(u64, u64)> {
+    (from..=to)
+        .step_by(size as usize)
+        .map(|start| (start, std::cmp::min(start + size - 1, to)))
+        .collect()
+}
+
+/// the transactions any source listed, once each and ordered by block
+#[derive(Debug)]
+crate struct Candidates {
+    crate hashes: Vec<(H256, u64)>,
+    /// the explorer records of each transaction
+    crate records: HashMap<H256, Vec<EtherScanRecord>>,
+}
+
+impl Candidates {
+    fn merge(listed: Vec<Appearance>) -> Candidates {
+        let mut records: HashMap<H256, Vec<EtherScanRecord>> = HashMap::new();
+        let mut hashes = Vec::new();
+        for appearance in listed {
+            hashes.push((appearance.hash, appearance.block));
+            if let Some(record) = appearance.record {
+                records.entry(appearance.hash).or_insert_with(Vec::new).push(record);
+            }
+        }
+        hashes.sort_by_key(|(_, block)| *block);
+        let hashes = hashes.into_iter().unique_by(|(hash, _)| *hash).collect();
+        Candidates { hashes, records }
+    }
+}
+
+/// ask every source for the transactions of `addr` in blocks `from..=to`, and merge what they list
+crate fn gather<T>(client: &mut Client<T>, sources: &[Box<dyn AppearanceSource<T>>], addr: H160, from: u64, to: u64)
+                   -> Result<Candidates, Error>
+where
+    T: BatchTransport + Send + Sync + 'static,
+{
+    let mut listed = Vec::new();
+    let mut by_source = Vec::new();
+    for source in sources {
+        // nothing is fetched yet, so there is no progress to save
+        if utils::interrupted() {
+            return Err(ErrorKind::Interrupted.into());
+        }
+        info!("gathering transactions in blocks {}..{} from {}", from, to, source.name());
+        let found = source.appearances(client, addr, from, to)?;
+        by_source.push((source.name(), found.iter().map(|a| a.hash).collect::<HashSet<H256>>()));
+        listed.extend(found);
+    }
+    // what only one source lists is what the others miss
+    if by_source.len() > 1 {
+        for (name, hashes) in by_source.iter() {
+            let only = hashes.iter()
+                .filter(|hash| by_source.iter().filter(|(_, other)| other.contains(*hash)).count() == 1)
+                .count();
+            info!("{} listed {} transactions, {} of them listed by no other source", name, hashes.len(), only);
+        }
+    }
+    Ok(Candidates::merge(listed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_hash_files() {
+        let contents = "# exported appearances\n\
+                        0x8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a2, 2000000\n\
+                        \n\
+                        8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a3\n";
+        let hashes = parse_hashes(contents).unwrap();
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[0].1, Some(2_000_000));
+        assert_eq!(hashes[1].1, None);
+        assert!(parse_hashes("0x1234").is_err());
+        assert!(parse_hashes("0x8d2b4ac3a5d3f4e7e9ff0f8b3dbbb10a1c1f8b8bdfbd1df3ac6da4e7bd5ff0a2,1,2").is_err());
+    }
+
+    #[test]
+    fn it_should_list_token_transfers_apart() {
+        let conf = crate::conf::ExplorerConf {
+            explorer: crate::conf::Explorer::EtherScan,
+            url: "http://localhost:4000/api".to_string(),
+            api_key: None,
+            requests_per_second: None,
+        };
+        let mut eth_scan = EtherScan::new(&conf).unwrap();
+        let with = AppearanceSource::<web3::transports::Http>::lists(&eth_scan);
+        eth_scan.token_transfers(false);
+        let without = AppearanceSource::<web3::transports::Http>::lists(&eth_scan);
+        assert_eq!(without, vec!["EtherScan".to_string()]);
+        assert!(without.iter().all(|s| !with.contains(s)));
+    }
+
+    #[test]
+    fn it_should_chunk_ranges() {
+        assert_eq!(chunks(0, 25_000, NODE_CHUNK), vec![(0, 9_999), (10_000, 19_999), (20_000, 25_000)]);
+        assert_eq!(chunks(5, 5, NODE_CHUNK), vec![(5, 5)]);
+        assert!(chunks(6, 5, NODE_CHUNK).is_empty());
+    }
+
+    #[test]
+    fn it_should_merge_appearances_once() {
+        let appearance = |n: u8, block| {
+            let mut hash = H256::zero();
+            hash[31] = n;
+            Appearance { hash, block, record: None }
+        };
+        let merged = Candidates::merge(vec![appearance(2, 20), appearance(1, 10), appearance(2, 20), appearance(3, 15)]);
+        let blocks = merged.hashes.iter().map(|(_, block)| *block).collect::<Vec<u64>>();
+        assert_eq!(blocks, vec![10, 15, 20]);
+        assert!(merged.records.is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! A content-addressed store of chain data, shared between the caches of every address
+//! Blocks, transactions, receipts and traces are each stored once, keyed by hash,
+//! no matter how many watched addresses appear in them.
+//! Tables are append-only segment files; only their keys are held in memory
+use log::*;
+use failure::{Error, ResultExt};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    path::{Path, PathBuf},
+};
+use web3::types::{Transaction, TransactionReceipt, Trace, H256, Block as Web3Block};
+use crate::{
+    err::ErrorKind,
+    etherscan::EtherScanRecord,
+};
+
+use super::{
+    simpledb::{SimpleDB, SegmentDB},
+    cache::{Tx, Block},
+};
+
+/// tables saved whole by `SimpleDB` before the store was segmented
+const LEGACY_TABLES: [&str; 4] = ["transactions", "receipts", "traces", "blocks"];
+
+/// Chain data shared by every address cache
+#[derive(Debug)]
+pub struct Store {
+    transactions: SegmentDB<H256, Transaction>,
+    receipts: SegmentDB<H256, TransactionReceipt>,
+    traces: SegmentDB<H256, Vec<Trace>>,
+    /// keyed by block hash
+    blocks: SegmentDB<H256, Web3Block<H256>>,
+    /// block number -> block hash
+    block_numbers: SegmentDB<u64, H256>,
+    /// what EtherScan listed for each transaction
+    explorer: SegmentDB<H256, Vec<EtherScanRecord>>,
+}
+
+impl Store {
+    /// open the store that lives in `dir`, creating it if it does not exist
+    crate fn open(dir: PathBuf) -> Result<Self, Error> {
+        if !dir.is_dir() {
+            std::fs::create_dir_all(dir.as_path()).context(ErrorKind::Cache)?;
+        }
+        let mut store = Self::load(dir.as_path(), false)?;
+        store.migrate(dir.as_path())?;
+        info!("Shared store holds {} transactions, {} blocks", store.transactions.len(), store.blocks.len());
+        Ok(store)
+    }
+
+    /// open the store that lives in `dir` without writing to it. A missing store is empty,
+    /// and tables saved before the store was segmented are not read
+    crate fn open_read_only(dir: PathBuf) -> Result<Self, Error> {
+        let store = Self::load(dir.as_path(), true)?;
+        if LEGACY_TABLES.iter().any(|name| dir.join(format!("{}.bin", name)).exists()) {
+            warn!("The shared store in {:?} has tables saved before it was segmented; \
+                   they are not read until a validation moves them into segments", dir);
+        }
+        Ok(store)
+    }
+
+    fn load(dir: &Path, read_only: bool) -> Result<Self, Error> {
+        Ok(Store {
+            transactions: table(dir, "transactions", read_only)?,
+            receipts: table(dir, "receipts", read_only)?,
+            traces: table(dir, "traces", read_only)?,
+            blocks: table(dir, "blocks", read_only)?,
+            block_numbers: table(dir, "block_numbers", read_only)?,
+            explorer: table(dir, "explorer", read_only)?,
+        })
+    }
+
+    /// move tables saved whole, before the store was segmented, into segments
+    fn migrate(&mut self, dir: &Path) -> Result<(), Error> {
+        if let Some(txs) = legacy::<Transaction>(dir, "transactions")? {
+            self.transactions.extend(txs).context(ErrorKind::Cache)?;
+        }
+        if let Some(receipts) = legacy::<TransactionReceipt>(dir, "receipts")? {
+            self.receipts.extend(receipts).context(ErrorKind::Cache)?;
+        }
+        if let Some(traces) = legacy::<Vec<Trace>>(dir, "traces")? {
+            self.traces.extend(traces).context(ErrorKind::Cache)?;
+        }
+        if let Some(blocks) = legacy::<Web3Block<H256>>(dir, "blocks")? {
+            let numbers = blocks.iter()
+                .filter_map(|(hash, block)| block.number.map(|n| (n.as_u64(), *hash)))
+                .collect::<Vec<(u64, H256)>>();
+            self.blocks.extend(blocks).context(ErrorKind::Cache)?;
+            self.block_numbers.extend(numbers).context(ErrorKind::Cache)?;
+        }
+        for name in LEGACY_TABLES.iter() {
+            let path = dir.join(format!("{}.bin", name));
+            if path.exists() {
+                std::fs::remove_file(path).context(ErrorKind::Cache)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// assemble everything known about the transaction `hash`
+    crate fn tx(&self, hash: &H256) -> Result<Tx, Error> {
+        let transaction = self.transactions.get(hash).context(ErrorKind::Cache)?;
+        let receipt = self.receipts.get(hash).context(ErrorKind::Cache)?;
+        let block_hash = transaction.as_ref().and_then(|t| t.block_hash)
+            .or_else(|| receipt.as_ref().and_then(|r| r.block_hash));
+        Ok(Tx {
+            traces: self.traces.get(hash).context(ErrorKind::Cache)?,
+            logs: receipt.as_ref().map(|r| r.logs.clone()),
+            block: self.block_for(hash, block_hash)?,
+            explorer: self.explorer.get(hash).context(ErrorKind::Cache)?,
+            transaction, receipt,
+        })
+    }
+
+    /// the (block number, transaction index) of `hash`, without reading its traces or block
+    crate fn position(&self, hash: &H256) -> Result<Option<(u64, usize)>, Error> {
+        let tx = Tx {
+            transaction: self.transactions.get(hash).context(ErrorKind::Cache)?,
+            receipt: self.receipts.get(hash).context(ErrorKind::Cache)?,
+            traces: None, logs: None, block: None, explorer: None,
+        };
+        Ok(tx.position())
+    }
+
+    /// the block `block_hash`, associated with the transaction `tx_hash`
+    crate fn block_for(&self, tx_hash: &H256, block_hash: Option<H256>) -> Result<Option<Block>, Error> {
+        let block_hash = match block_hash {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        let block = self.blocks.get(&block_hash).context(ErrorKind::Cache)?;
+        Ok(block.map(|block| Block { tx_hash: *tx_hash, block }))
+    }
+
+    crate fn has_transaction(&self, hash: &H256) -> bool {
+        self.transactions.contains(hash)
+    }
+
+    crate fn has_receipt(&self, hash: &H256) -> bool {
+        self.receipts.contains(hash)
+    }
+
+    crate fn has_traces(&self, hash: &H256) -> bool {
+        self.traces.contains(hash)
+    }
+
+    crate fn has_block(&self, block_num: u64) -> bool {
+        self.block_numbers.contains(&block_num)
+    }
+
+    /// append every part of `txs` to the store. Parts that are already stored are kept
+    crate fn absorb(&mut self, txs: impl IntoIterator<Item = (H256, Tx)>) -> Result<(), Error> {
+        self.write(txs, false)
+    }
+
+    /// append every part of `txs` to the store, replacing the parts that are already stored
+    crate fn overwrite(&mut self, txs: impl IntoIterator<Item = (H256, Tx)>) -> Result<(), Error> {
+        self.write(txs, true)
+    }
+
+    fn write(&mut self, txs: impl IntoIterator<Item = (H256, Tx)>, replace: bool) -> Result<(), Error> {
+        let (mut transactions, mut receipts, mut traces, mut blocks, mut numbers, mut explorer) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (hash, tx) in txs {
+            if let Some(t) = tx.transaction {
+                transactions.push((hash, t));
+            }
+            if let Some(r) = tx.receipt {
+                receipts.push((hash, r));
+            }
+            if let Some(t) = tx.traces {
+                traces.push((hash, t));
+            }
+            if let Some(b) = tx.block {
+                if let (Some(block_hash), Some(num)) = (b.block.hash, b.block.number) {
+                    numbers.push((num.as_u64(), block_hash));
+                    blocks.push((block_hash, b.block));
+                }
+            }
+            if let Some(records) = tx.explorer {
+                explorer.push((hash, records));
+            }
+        }
+        put(&mut self.transactions, transactions, replace)?;
+        put(&mut self.receipts, receipts, replace)?;
+        put(&mut self.traces, traces, replace)?;
+        put(&mut self.blocks, blocks, replace)?;
+        put(&mut self.block_numbers, numbers, replace)?;
+        put(&mut self.explorer, explorer, replace)?;
+        Ok(())
+    }
+}
+
+/// append `records` to `table`, replacing stored records if `replace` is set
+fn put<K, V>(table: &mut SegmentDB<K, V>, records: Vec<(K, V)>, replace: bool) -> Result<(), Error>
+where
+    K: DeserializeOwned + Serialize + Eq + Hash + Clone,
+    V: DeserializeOwned + Serialize + Clone,
+{
+    if replace {
+        table.overwrite(records).context(ErrorKind::Cache)?;
+    } else {
+        table.extend(records).context(ErrorKind::Cache)?;
+    }
+    Ok(())
+}
+
+/// the segmented table `name` of the store in `dir`
+fn table<K, V>(dir: &Path, name: &str, read_only: bool) -> Result<SegmentDB<K, V>, Error>
+where
+    K: DeserializeOwned + Serialize + Eq + Hash + Clone,
+    V: DeserializeOwned + Serialize + Clone,
+{
+    let path = dir.join(format!("{}.seg", name));
+    let db = if read_only { SegmentDB::open_read_only(path) } else { SegmentDB::open(path) };
+    Ok(db.context(ErrorKind::Cache)?)
+}
+
+/// a table written by `SimpleDB` before the store was segmented
+fn legacy<V>(dir: &Path, name: &str) -> Result<Option<HashMap<H256, V>>, Error>
+where
+    V: DeserializeOwned + Serialize
+{
+    let path = dir.join(format!("{}.bin", name));
+    if !path.exists() {
+        return Ok(None);
+    }
+    info!("Moving {:?} into segments", path);
+    let db = SimpleDB::<HashMap<H256, V>>::new(path)?;
+    Ok(Some(db.get().context(ErrorKind::Cache)?))
+}
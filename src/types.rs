@@ -1,6 +1,6 @@
 pub const INFURA_URL: &str = "https://mainnet.infura.io/";
 
-pub const ETHERSCAN_URL: &str = "http://api.etherscan.io/api";
+pub const ETHERSCAN_URL: &str = "https://api.etherscan.io/api";
 
 pub const MAX_PARALLEL_REQUESTS: usize = 128;
 
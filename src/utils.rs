@@ -1,6 +1,15 @@
+use log::*;
 use web3::BatchTransport;
 use futures::future::Future;
+use failure::{Error, ResultExt};
+use std::sync::{Once, ONCE_INIT, atomic::{AtomicBool, Ordering}};
 use super::client::Client;
+use super::err::ErrorKind;
+
+/// set by the SIGINT handler
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// set while an `InterruptGuard` is alive
+static CATCHING: AtomicBool = AtomicBool::new(false);
 /*
 macro_rules! replace_expr {
     ($_t:tt $sub:expr) => {$sub};
@@ -105,14 +114,47 @@ where
     b.as_u64()
 }
 
-pub fn as_u64<T>(client: &Client<T>, block: web3::types::BlockNumber) -> u64
-where
-    T: BatchTransport
-{
+/// resolve a block to a concrete number, using `latest` as the pinned chain head.
+/// `latest` should be queried once with `latest_block` so that every user of the
+/// resolved number agrees on where the chain ended
+pub fn pin_block(block: web3::types::BlockNumber, latest: u64) -> u64 {
     match block {
         web3::types::BlockNumber::Earliest => 0 as u64,
-        web3::types::BlockNumber::Latest => latest_block(client),
-        web3::types::BlockNumber::Pending => latest_block(client),
-        web3::types::BlockNumber::Number(num)=> num,
+        web3::types::BlockNumber::Latest => latest,
+        web3::types::BlockNumber::Pending => latest,
+        web3::types::BlockNumber::Number(num) => num,
+    }
+}
+
+/// On SIGINT, ask long-running work to save its progress and stop, instead of exiting straight away.
+/// A second SIGINT exits immediately. Once the returned guard is dropped, SIGINT exits straight away again
+pub fn catch_interrupt() -> Result<InterruptGuard, Error> {
+    static INSTALL: Once = ONCE_INIT;
+    let mut res = Ok(());
+    INSTALL.call_once(|| {
+        // the handler can not be uninstalled, so it exits by itself when nothing is catching
+        res = ctrlc::set_handler(|| {
+            if !CATCHING.load(Ordering::SeqCst) || INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            warn!("Interrupted; saving progress. Interrupt again to exit immediately");
+        }).context(ErrorKind::Internal).map_err(Error::from);
+    });
+    res?;
+    CATCHING.store(true, Ordering::SeqCst);
+    Ok(InterruptGuard(()))
+}
+
+/// SIGINT is caught by `catch_interrupt` for as long as this is alive
+pub struct InterruptGuard(());
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        CATCHING.store(false, Ordering::SeqCst);
     }
 }
+
+/// whether SIGINT was received since `catch_interrupt`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}